@@ -0,0 +1,9 @@
+//! The engine without the board: speaks UCI on stdin and stdout, for chess GUIs and testing
+//! tools.
+
+use std::io;
+
+fn main() {
+    let stdin = io::stdin();
+    rustic_chess::uci::run(stdin.lock(), io::stdout());
+}
@@ -0,0 +1,152 @@
+//! Sets of squares as 64 bit integers, and the attack tables `Position` generates moves with.
+//!
+//! Bit `8 * rank + file` stands for a square: a1 is bit 0, h1 bit 7 and h8 bit 63. Knight,
+//! king and pawn attacks are looked up in tables built at compile time; sliding pieces use
+//! hyperbola quintessence along the lines through their square.
+
+use crate::game::{Coord, PieceColor};
+
+pub type Bitboard = u64;
+
+pub const FILE_A: Bitboard = 0x0101_0101_0101_0101;
+pub const FILE_H: Bitboard = FILE_A << 7;
+
+pub fn square_index(square: Coord) -> usize {
+    square.0 as usize * 8 + square.1 as usize
+}
+
+pub fn index_square(index: usize) -> Coord {
+    ((index / 8) as u8, (index % 8) as u8)
+}
+
+pub fn square_bit(square: Coord) -> Bitboard {
+    1 << square_index(square)
+}
+
+/// Squares in `bitboard`, from a1 up to h8
+pub fn squares(mut bitboard: Bitboard) -> impl Iterator<Item = Coord> {
+    std::iter::from_fn(move || {
+        if bitboard == 0 {
+            return None;
+        }
+        let index = bitboard.trailing_zeros() as usize;
+        bitboard &= bitboard - 1;
+        Some(index_square(index))
+    })
+}
+
+// Tables ======================================================================================= //
+/// For every square, the squares one of `steps` away that are still on the board
+const fn step_table(steps: &[(i8, i8)]) -> [Bitboard; 64] {
+    let mut table = [0; 64];
+    let mut index = 0;
+    while index < 64 {
+        let mut i = 0;
+        while i < steps.len() {
+            let rank = (index / 8) as i8 + steps[i].0;
+            let file = (index % 8) as i8 + steps[i].1;
+            if rank >= 0 && rank < 8 && file >= 0 && file < 8 {
+                table[index] |= 1 << (rank * 8 + file) as u32;
+            }
+            i += 1;
+        }
+        index += 1;
+    }
+    table
+}
+
+/// For every square, the other squares on the line through it going `direction` both ways
+const fn line_table(direction: (i8, i8)) -> [Bitboard; 64] {
+    let mut table = [0; 64];
+    let mut index = 0;
+    while index < 64 {
+        let mut sign = -1;
+        while sign <= 1 {
+            let mut rank = (index / 8) as i8 + sign * direction.0;
+            let mut file = (index % 8) as i8 + sign * direction.1;
+            while rank >= 0 && rank < 8 && file >= 0 && file < 8 {
+                table[index] |= 1 << (rank * 8 + file) as u32;
+                rank += sign * direction.0;
+                file += sign * direction.1;
+            }
+            sign += 2;
+        }
+        index += 1;
+    }
+    table
+}
+
+const KNIGHT_ATTACKS: [Bitboard; 64] = step_table(&[
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+]);
+const KING_ATTACKS: [Bitboard; 64] = step_table(&[
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+]);
+const WHITE_PAWN_ATTACKS: [Bitboard; 64] = step_table(&[(1, -1), (1, 1)]);
+const BLACK_PAWN_ATTACKS: [Bitboard; 64] = step_table(&[(-1, -1), (-1, 1)]);
+
+const RANKS: [Bitboard; 64] = line_table((0, 1));
+const FILES: [Bitboard; 64] = line_table((1, 0));
+const DIAGONALS: [Bitboard; 64] = line_table((1, 1));
+const ANTI_DIAGONALS: [Bitboard; 64] = line_table((1, -1));
+
+// Attacks ====================================================================================== //
+pub fn knight_attacks(square: Coord) -> Bitboard {
+    KNIGHT_ATTACKS[square_index(square)]
+}
+
+pub fn king_attacks(square: Coord) -> Bitboard {
+    KING_ATTACKS[square_index(square)]
+}
+
+/// Squares a pawn of `color` on `square` takes on
+pub fn pawn_attacks(square: Coord, color: PieceColor) -> Bitboard {
+    match color {
+        PieceColor::White => WHITE_PAWN_ATTACKS[square_index(square)],
+        PieceColor::Black => BLACK_PAWN_ATTACKS[square_index(square)],
+    }
+}
+
+/// Hyperbola quintessence: the squares of `line` a slider on `square` reaches, up to and
+/// including the first occupied one each way. Subtracting the slider from the blockers
+/// flips every bit up to the nearest blocker above it; doing the same on the reversed board
+/// finds the nearest one below.
+fn line_attacks(square: Coord, occupied: Bitboard, line: Bitboard) -> Bitboard {
+    let slider = square_bit(square);
+    let blockers = occupied & line;
+    let above = blockers.wrapping_sub(slider);
+    let below = blockers
+        .reverse_bits()
+        .wrapping_sub(slider.reverse_bits())
+        .reverse_bits();
+    (above ^ below) & line
+}
+
+pub fn bishop_attacks(square: Coord, occupied: Bitboard) -> Bitboard {
+    let index = square_index(square);
+    line_attacks(square, occupied, DIAGONALS[index])
+        | line_attacks(square, occupied, ANTI_DIAGONALS[index])
+}
+
+pub fn rook_attacks(square: Coord, occupied: Bitboard) -> Bitboard {
+    let index = square_index(square);
+    line_attacks(square, occupied, RANKS[index]) | line_attacks(square, occupied, FILES[index])
+}
+
+pub fn queen_attacks(square: Coord, occupied: Bitboard) -> Bitboard {
+    bishop_attacks(square, occupied) | rook_attacks(square, occupied)
+}
@@ -1,11 +1,22 @@
+use crate::clock::{Clock, GameClock};
+use crate::external::ExternalEngine;
+use crate::fen::to_fen;
+use crate::game::{Game, GameOver, Move, Position};
+use crate::notation::{move_number, to_san};
+use crate::opponent::EngineOpponent;
+use crate::pgn::{save_pgn, PgnHeaders};
 use crate::pieces::*;
-use bevy::app::AppExit;
+use crate::replay::Replay;
 use bevy::prelude::*;
 use bevy_mod_picking::{Group, PickState, PickableMesh};
+use std::path::Path;
 
 const ROWS: u8 = 8;
 const COLS: u8 = 8;
 
+/// Where finished games are saved as PGN
+const GAMES_DIRECTORY: &str = "games";
+
 #[derive(Debug)]
 pub struct Square {
     pub x: u8,
@@ -57,11 +68,31 @@ pub fn create_board(
     }
 }
 
+/// What `color_squares` highlights besides the cursor and the selection, worked out again
+/// only when the selection or the position on the board changes
+#[derive(Default)]
+struct SquareHighlights {
+    piece: Option<Entity>,
+    review: Option<usize>,
+    position: Position,
+    /// Legal target squares of the selected piece, and whether moving there takes a piece
+    destinations: Vec<((u8, u8), bool)>,
+    /// The move that led to the position on the board
+    last_move: Option<Move>,
+    /// Square of the king of the side to move, if it is in check
+    king_in_check: Option<(u8, u8)>,
+}
+
 fn color_squares(
     pick_state: Res<PickState>,
     selected_square: Res<SelectedSquare>,
+    selected_piece: Res<SelectedPiece>,
+    game: Res<Game>,
+    review: Res<ReviewPly>,
+    mut highlights: Local<SquareHighlights>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     query: Query<(Entity, &Square, &Handle<StandardMaterial>)>,
+    pieces_query: Query<&Piece>,
 ) {
     // Get entity under the cursor, if there is one
     let top_entity = if let Some((entity, _intersection)) = pick_state.top(Group::default()) {
@@ -70,15 +101,71 @@ fn color_squares(
         None
     };
 
+    if highlights.piece != selected_piece.entity
+        || highlights.review != review.0
+        || highlights.position != *game.position()
+    {
+        let (shown, shown_ply) = match review.0 {
+            Some(ply) => (game.position_at(ply), ply),
+            None => (game.position(), game.moves().len()),
+        };
+
+        // Nothing can be played while reviewing, so no destinations are shown
+        let selected = selected_piece
+            .entity
+            .filter(|_| review.0.is_none())
+            .and_then(|entity| pieces_query.get(entity).ok());
+        let destinations = match selected {
+            Some(piece) => shown
+                .legal_moves_from((piece.x, piece.y))
+                .into_iter()
+                .map(|mv| (mv.to, shown.is_capture(mv)))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        *highlights = SquareHighlights {
+            piece: selected_piece.entity,
+            review: review.0,
+            position: game.position().clone(),
+            destinations,
+            last_move: shown_ply.checked_sub(1).map(|ply| game.moves()[ply]),
+            king_in_check: shown
+                .king_square(shown.turn())
+                .filter(|_| shown.is_in_check(shown.turn())),
+        };
+    }
+
     for (entity, square, material_handle) in query.iter() {
         // Get the actual material
         let material = materials.get_mut(material_handle).unwrap();
+        let coord = (square.x, square.y);
+        let destination = highlights
+            .destinations
+            .iter()
+            .find(|(to, _)| *to == coord);
+        let in_last_move =
+            matches!(highlights.last_move, Some(mv) if mv.from == coord || mv.to == coord);
 
         // Change the material color
         material.albedo = if Some(entity) == top_entity {
             Color::rgb(0.8, 0.3, 0.3)
         } else if Some(entity) == selected_square.entity {
             Color::rgb(0.9, 0.1, 0.1)
+        } else if let Some((_, is_capture)) = destination {
+            if *is_capture {
+                Color::rgb(0.9, 0.5, 0.1)
+            } else {
+                Color::rgb(0.3, 0.7, 0.3)
+            }
+        } else if highlights.king_in_check == Some(coord) {
+            Color::rgb(1., 0., 0.)
+        } else if in_last_move {
+            if square.is_white() {
+                Color::rgb(0.9, 0.9, 0.5)
+            } else {
+                Color::rgb(0.4, 0.4, 0.1)
+            }
         } else if square.is_white() {
             Color::rgb(1., 0.9, 0.9)
         } else {
@@ -92,12 +179,28 @@ impl Plugin for BoardPlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.init_resource::<SelectedSquare>()
             .init_resource::<SelectedPiece>()
+            .init_resource::<Game>()
             .init_resource::<PlayerTurn>()
+            .init_resource::<PendingPromotion>()
+            .init_resource::<ReviewPly>()
             .add_event::<ResetSelectedEvent>()
+            .add_event::<GameOver>()
+            .add_event::<PlayMove>()
+            .add_event::<ResyncPieces>()
+            .add_event::<UndoRedo>()
             .add_startup_system(create_board.system())
             .add_system(color_squares.system())
             .add_system(select_square.system())
             .add_system(move_piece.system())
+            .add_system(play_moves.system())
+            .add_system(resync_pieces.system())
+            .add_system(leave_review.system())
+            .add_system(claim_draw.system())
+            .add_system(undo_redo_keys.system())
+            .add_system(undo_redo.system())
+            .add_system(new_game.system())
+            .add_system(export_fen.system())
+            .add_system(save_game.system())
             .add_system(select_piece.system())
             .add_system(despawn_taken_pieces.system())
             .add_system(reset_selected.system());
@@ -162,16 +265,28 @@ fn select_piece(
 }
 
 fn move_piece(
-    commands: &mut Commands,
     selected_square: ChangedRes<SelectedSquare>,
     selected_piece: Res<SelectedPiece>,
-    mut turn: ResMut<PlayerTurn>,
+    game: Res<Game>,
+    mut pending_promotion: ResMut<PendingPromotion>,
+    replay: Res<Replay>,
+    review: Res<ReviewPly>,
+    opponent: Res<EngineOpponent>,
     squares_query: Query<&Square>,
-    mut pieces_query: Query<(Entity, &mut Piece)>,
+    pieces_query: Query<&Piece>,
     mut reset_selected_event: ResMut<Events<ResetSelectedEvent>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    asset_server: Res<AssetServer>
+    mut play_move_events: ResMut<Events<PlayMove>>,
 ) {
+    // Wait for the promotion dialog to be answered, keep replays and reviews read-only, and
+    // leave the engine's moves to the engine
+    if pending_promotion.0.is_some()
+        || replay.is_active()
+        || review.0.is_some()
+        || opponent.plays(game.turn())
+    {
+        return;
+    }
+
     let square_entity = if let Some(entity) = selected_square.entity {
         entity
     } else {
@@ -185,106 +300,357 @@ fn move_piece(
     };
 
     if let Some(selected_piece_entity) = selected_piece.entity {
-        let pieces_vec = pieces_query.iter_mut().map(|(_, piece)| *piece).collect();
+        let from = if let Ok(piece) = pieces_query.get(selected_piece_entity) {
+            (piece.x, piece.y)
+        } else {
+            return;
+        };
+        let to = (square.x, square.y);
+
+        // A pawn reaching the last rank pauses until the player picks a piece
+        if game.is_promotion(from, to) {
+            pending_promotion.0 = Some(Move::new(from, to));
+        } else {
+            play_move_events.send(PlayMove(Move::new(from, to)));
+        }
+
+        reset_selected_event.send(ResetSelectedEvent);
+    }
+}
+
+/// Asks `play_moves` to play a move. Clicks on the board and the promotion dialog both end up
+/// here, and the rules have the final word on whether the move happens.
+pub struct PlayMove(pub Move);
+
+/// A promotion move waiting for the player to choose a piece in the UI
+#[derive(Default)]
+pub struct PendingPromotion(pub Option<Move>);
+
+/// While `Some(ply)`, the board shows the position after that many half moves instead of the
+/// current one, and can't be played on
+#[derive(Default)]
+pub struct ReviewPly(pub Option<usize>);
+
+fn play_moves(
+    commands: &mut Commands,
+    mut event_reader: Local<EventReader<PlayMove>>,
+    events: Res<Events<PlayMove>>,
+    mut game: ResMut<Game>,
+    mut turn: ResMut<PlayerTurn>,
+    mut review: ResMut<ReviewPly>,
+    mut clock: ResMut<GameClock>,
+    piece_assets: Res<PieceAssets>,
+    mut pieces_query: Query<(Entity, &mut Piece)>,
+    mut game_over_events: ResMut<Events<GameOver>>,
+    mut resync_events: ResMut<Events<ResyncPieces>>,
+) {
+    for PlayMove(mv) in event_reader.iter(&events) {
+        // Name the move before it changes the position it is named in
+        let logged = format!(
+            "{} {}",
+            move_number(game.position()),
+            to_san(game.position(), *mv)
+        );
+
+        // Let the rules decide, then mirror whatever they did on the entities
+        let effects = if let Ok(effects) = game.make_move(*mv) {
+            effects
+        } else {
+            continue;
+        };
+        println!("{}", logged);
+
+        if let Some(clock) = clock.0.as_mut() {
+            clock.press(game.turn().opposite());
+        }
+        if let Some(game_over) = game.outcome() {
+            game_over_events.send(game_over);
+        }
+        turn.0 = game.turn();
+
+        // The board shows an older position, so there is nothing to mirror: go back to the
+        // current one
+        if review.0.take().is_some() {
+            resync_events.send(ResyncPieces);
+            continue;
+        }
+
         let pieces_entity_vec = pieces_query
             .iter_mut()
             .map(|(entity, piece)| (entity, *piece))
             .collect::<Vec<(Entity, Piece)>>();
-        // Move the selected piece to the selected square
-        let mut piece =
-            if let Ok((_piece_entity, piece)) = pieces_query.get_mut(selected_piece_entity) {
-                piece
-            } else {
-                return;
-            };
-
-
-        if piece.is_move_valid((square.x, square.y), pieces_vec) {
-
-            // perform castling
-            match piece.piece_type{
-                PieceType::King =>{
-                    if piece.x == square.x && piece.y == 4 && square.y == 6{
-                        // move king to its new position
-                        piece.y = 6;
-
-                        // find corresponding rook, take it out and respawn it to its new position
-                        for (other_entity, other_piece) in &pieces_entity_vec{
-                            if other_piece.x == square.x &&
-                                other_piece.y == 7 &&
-                                other_piece.piece_type == PieceType::Rook &&
-                                other_piece.color == piece.color{
-
-                                    // take the castle rook out
-                                    commands.insert_one(*other_entity, Taken);
-
-                                    // respawn rook at its new position
-                                    spawn_rook(
-                                        commands,
-                                        match piece.color{
-                                            PieceColor::Black => materials.add(Color::rgb(0., 0.2, 0.2).into()),
-                                            PieceColor::White => materials.add(Color::rgb(1., 0.8, 0.8).into()),
-                                        },
-                                        piece.color,
-                                        asset_server.load("models/chess_kit/pieces.glb#Mesh5/Primitive0"),
-                                        (piece.x, 5)
-                                    );
-                                }
-                        }
+        let entity_at = |position: (u8, u8)| {
+            pieces_entity_vec
+                .iter()
+                .find(|(_, piece)| (piece.x, piece.y) == position)
+                .map(|(entity, _)| *entity)
+        };
 
-                        // toggle turn
-                        turn.change();
-                        return;
+        if let Some(captured) = effects.captured {
+            if let Some(other_entity) = entity_at(captured) {
+                // Mark the piece as taken
+                commands.insert_one(other_entity, Taken);
+            }
+        }
+
+        // Move pieces, both the king and the rook when castling, so `move_pieces` animates them
+        for (moved_from, moved_to) in effects.relocated {
+            if let Some(moved_entity) = entity_at(moved_from) {
+                match effects.promoted {
+                    // Replace the pawn with the piece it promoted to
+                    Some((square, promoted)) if square == moved_to => {
+                        commands.insert_one(moved_entity, Taken);
+                        spawn_piece(commands, &piece_assets, promoted, moved_to);
                     }
-                    else if piece.x == square.x && piece.y == 4 && square.y == 2{
-                                                // find corresponding rook, take it out and respawn it to its new position
-                        for (other_entity, other_piece) in &pieces_entity_vec{
-                            if other_piece.x == square.x &&
-                                other_piece.y == 0 &&
-                                other_piece.piece_type == PieceType::Rook &&
-                                other_piece.color == piece.color{
-
-                                    // take the castle rook out
-                                    commands.insert_one(*other_entity, Taken);
-
-                                    // respawn rook at its new position
-                                    spawn_rook(
-                                        commands,
-                                        match piece.color{
-                                            PieceColor::Black => materials.add(Color::rgb(0., 0.2, 0.2).into()),
-                                            PieceColor::White => materials.add(Color::rgb(1., 0.8, 0.8).into()),
-                                        },
-                                        piece.color,
-                                        asset_server.load("models/chess_kit/pieces.glb#Mesh5/Primitive0"),
-                                        (piece.x, 3)
-                                    );
-                                }
+                    _ => {
+                        if let Ok((_, mut piece)) = pieces_query.get_mut(moved_entity) {
+                            piece.x = moved_to.0;
+                            piece.y = moved_to.1;
                         }
                     }
                 }
-                _ => {}
             }
+        }
+    }
+}
 
-            // Check if a piece of the opposite color exists in this square and despawn it
-            for (other_entity, other_piece) in pieces_entity_vec {
-                if other_piece.x == square.x
-                    && other_piece.y == square.y
-                    && other_piece.color != piece.color
-                {
-                    // Mark the piece as taken
-                    commands.insert_one(other_entity, Taken);
-                }
+/// Let the players claim a threefold repetition or fifty-move draw with the D key
+fn claim_draw(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut game: ResMut<Game>,
+    mut game_over_events: ResMut<Events<GameOver>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::D) {
+        return;
+    }
+
+    if let Some(game_over) = game.claim_draw() {
+        game_over_events.send(game_over);
+    }
+}
+
+/// Taking back the last move, or playing a taken back move again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndoRedo {
+    Undo,
+    Redo,
+}
+
+/// Ctrl+Z takes back a move, Ctrl+Y or Ctrl+Shift+Z plays it again
+fn undo_redo_keys(keyboard_input: Res<Input<KeyCode>>, mut events: ResMut<Events<UndoRedo>>) {
+    let pressed = |keys: &[KeyCode]| keys.iter().any(|key| keyboard_input.pressed(*key));
+    if !pressed(&[KeyCode::LControl, KeyCode::RControl]) {
+        return;
+    }
+
+    let shift = pressed(&[KeyCode::LShift, KeyCode::RShift]);
+    if keyboard_input.just_pressed(KeyCode::Z) {
+        events.send(if shift { UndoRedo::Redo } else { UndoRedo::Undo });
+    } else if keyboard_input.just_pressed(KeyCode::Y) {
+        events.send(UndoRedo::Redo);
+    }
+}
+
+/// Undo or redo moves in the game and on the clock, then let `resync_pieces` put the entities
+/// back, captured pieces included. Against the engine, both its move and the player's go at once.
+fn undo_redo(
+    mut event_reader: Local<EventReader<UndoRedo>>,
+    events: Res<Events<UndoRedo>>,
+    mut game: ResMut<Game>,
+    mut clock: ResMut<GameClock>,
+    replay: Res<Replay>,
+    opponent: Res<EngineOpponent>,
+    mut review: ResMut<ReviewPly>,
+    mut pending_promotion: ResMut<PendingPromotion>,
+    mut resync_events: ResMut<Events<ResyncPieces>>,
+    mut game_over_events: ResMut<Events<GameOver>>,
+) {
+    // Replays have their own controls
+    if replay.is_active() {
+        return;
+    }
+
+    let mut changed = false;
+    for action in event_reader.iter(&events) {
+        let step = |game: &mut Game| match action {
+            UndoRedo::Undo => game.undo(),
+            UndoRedo::Redo => game.redo(),
+        };
+        if step(&mut *game).is_none() {
+            continue;
+        }
+        // Stopping on the engine's turn would only have it play again, and playing forgets
+        // the moves taken back
+        if opponent.plays(game.turn()) {
+            step(&mut *game);
+        }
+
+        changed = true;
+        if *action == UndoRedo::Redo {
+            if let Some(game_over) = game.outcome() {
+                game_over_events.send(game_over);
             }
+        }
+    }
 
-            // Move piece
-            piece.x = square.x;
-            piece.y = square.y;
+    if changed {
+        if let Some(clock) = clock.0.as_mut() {
+            clock.go_to(game.moves().len());
+        }
+        review.0 = None;
+        pending_promotion.0 = None;
+        resync_events.send(ResyncPieces);
+    }
+}
 
-            // Change turn
-            turn.change();
+/// Ctrl+N starts over from the position the game started from, on fresh clocks and with
+/// engines that forgot the last game
+fn new_game(
+    keyboard_input: Res<Input<KeyCode>>,
+    replay: Res<Replay>,
+    mut game: ResMut<Game>,
+    mut clock: ResMut<GameClock>,
+    mut opponent: ResMut<EngineOpponent>,
+    mut external: ResMut<ExternalEngine>,
+    mut review: ResMut<ReviewPly>,
+    mut pending_promotion: ResMut<PendingPromotion>,
+    mut resync_events: ResMut<Events<ResyncPieces>>,
+) {
+    let control = keyboard_input.pressed(KeyCode::LControl)
+        || keyboard_input.pressed(KeyCode::RControl);
+    if !control || !keyboard_input.just_pressed(KeyCode::N) || replay.is_active() {
+        return;
+    }
+
+    *game = Game::from_position(game.starting_position().clone());
+    if let Some(running) = clock.0.as_mut() {
+        *running = Clock::new(running.control().clone());
+    }
+    opponent.clear_hash();
+    external.new_game();
+    review.0 = None;
+    pending_promotion.0 = None;
+    resync_events.send(ResyncPieces);
+}
+
+/// Print the current position as FEN with the F key, and keep a copy in `position.fen`
+fn export_fen(keyboard_input: Res<Input<KeyCode>>, game: Res<Game>) {
+    if !keyboard_input.just_pressed(KeyCode::F) {
+        return;
+    }
+
+    let fen = to_fen(game.position());
+    println!("{}", fen);
+    if let Err(error) = std::fs::write("position.fen", format!("{}\n", fen)) {
+        eprintln!("Could not write position.fen: {}", error);
+    }
+}
+
+/// Save the game as PGN into `games/` when it ends, or whenever the P key is pressed. A game
+/// that ends again after undo and redo is only saved the first time.
+fn save_game(
+    mut event_reader: Local<EventReader<GameOver>>,
+    events: Res<Events<GameOver>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    game: Res<Game>,
+    mut saved_game_over: Local<Option<(Position, Vec<Move>)>>,
+) {
+    let requested = keyboard_input.just_pressed(KeyCode::P);
+    if event_reader.iter(&events).last().is_some() {
+        let finished = (game.starting_position().clone(), game.moves().to_vec());
+        if saved_game_over.as_ref() == Some(&finished) && !requested {
+            return;
         }
+        *saved_game_over = Some(finished);
+    } else if !requested {
+        return;
+    }
 
-        reset_selected_event.send(ResetSelectedEvent);
+    match save_pgn(&game, &PgnHeaders::default(), Path::new(GAMES_DIRECTORY)) {
+        Ok(path) => println!("Saved game to {}", path.display()),
+        Err(error) => eprintln!("Could not save game: {}", error),
+    }
+}
+
+/// Asks `resync_pieces` to make the entities match `Game` again after the game was replaced
+/// wholesale, e.g. when stepping through a replay
+pub struct ResyncPieces;
+
+fn resync_pieces(
+    commands: &mut Commands,
+    mut event_reader: Local<EventReader<ResyncPieces>>,
+    events: Res<Events<ResyncPieces>>,
+    game: Res<Game>,
+    review: Res<ReviewPly>,
+    mut turn: ResMut<PlayerTurn>,
+    piece_assets: Res<PieceAssets>,
+    mut pieces_query: Query<(Entity, &mut Piece), Without<Taken>>,
+    mut reset_selected_event: ResMut<Events<ResetSelectedEvent>>,
+) {
+    if event_reader.iter(&events).last().is_none() {
+        return;
+    }
+
+    // Squares still waiting for their piece, and entities not standing where they belong
+    let position = match review.0 {
+        Some(ply) => game.position_at(ply),
+        None => game.position(),
+    };
+    let mut missing: Vec<((u8, u8), BoardPiece)> = position.pieces().collect();
+    let mut misplaced = Vec::new();
+    for (entity, piece) in pieces_query.iter_mut() {
+        let board_piece = BoardPiece::new(piece.color, piece.piece_type);
+        let square = (piece.x, piece.y);
+        match missing.iter().position(|wanted| *wanted == (square, board_piece)) {
+            Some(index) => {
+                missing.swap_remove(index);
+            }
+            None => misplaced.push((entity, board_piece, square)),
+        }
+    }
+
+    // Slide misplaced pieces to the nearest square that wants one of their kind so that
+    // `move_pieces` animates them, and take the rest off
+    let distance = |a: (u8, u8), b: (u8, u8)| {
+        (a.0 as i8 - b.0 as i8).abs() + (a.1 as i8 - b.1 as i8).abs()
+    };
+    for (entity, board_piece, square) in misplaced {
+        let nearest = missing
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, wanted))| *wanted == board_piece)
+            .min_by_key(|(_, (target, _))| distance(square, *target))
+            .map(|(index, _)| index);
+        match nearest {
+            Some(index) => {
+                let (target, _) = missing.swap_remove(index);
+                if let Ok((_, mut piece)) = pieces_query.get_mut(entity) {
+                    piece.x = target.0;
+                    piece.y = target.1;
+                }
+            }
+            None => {
+                commands.insert_one(entity, Taken);
+            }
+        }
+    }
+
+    for (square, piece) in missing {
+        spawn_piece(commands, &piece_assets, piece, square);
+    }
+
+    turn.0 = game.turn();
+    reset_selected_event.send(ResetSelectedEvent);
+}
+
+/// Escape leaves a review and shows the current position again
+fn leave_review(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut review: ResMut<ReviewPly>,
+    mut resync_events: ResMut<Events<ResyncPieces>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Escape) && review.0.take().is_some() {
+        resync_events.send(ResyncPieces);
     }
 }
 
@@ -307,22 +673,9 @@ struct Taken;
 
 fn despawn_taken_pieces(
     commands: &mut Commands,
-    mut app_exit_events: ResMut<Events<AppExit>>,
     query: Query<(Entity, &Piece, &Taken)>,
 ) {
-    for (entity, piece, _taken) in query.iter() {
-        // If the king is taken, we should exit
-        if piece.piece_type == PieceType::King {
-            println!(
-                "{} won! Thanks for playing!",
-                match piece.color {
-                    PieceColor::White => "Black",
-                    PieceColor::Black => "White",
-                }
-            );
-            app_exit_events.send(AppExit);
-        }
-
+    for (entity, _piece, _taken) in query.iter() {
         // Despawn piece and children
         commands.despawn_recursive(entity);
     }
@@ -330,17 +683,10 @@ fn despawn_taken_pieces(
 
 // Turns ======================================================================================== //
 pub(crate) struct PlayerTurn(pub(crate) PieceColor);
-impl Default for PlayerTurn {
-    fn default() -> Self {
-        Self(PieceColor::White)
+impl FromResources for PlayerTurn {
+    fn from_resources(resources: &Resources) -> Self {
+        // The game may start from a FEN with black to move
+        Self(resources.get::<Game>().map_or(PieceColor::White, |game| game.turn()))
     }
 }
 
-impl PlayerTurn {
-    fn change(&mut self) {
-        self.0 = match self.0 {
-            PieceColor::White => PieceColor::Black,
-            PieceColor::Black => PieceColor::White,
-        }
-    }
-}
@@ -0,0 +1,440 @@
+//! Chess clocks: sudden death, Fischer increment, Bronstein and simple delay, and controls
+//! made of several periods such as 40 moves in 90 minutes followed by 30 minutes.
+
+use crate::board::PlayerTurn;
+use crate::game::{Game, GameOver, PieceColor};
+use crate::replay::Replay;
+use bevy::prelude::*;
+use std::fmt;
+use std::time::Duration;
+
+// Time controls ================================================================================ //
+/// Part of a time control: `time` to make `moves` moves in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Period {
+    /// `None` for the rest of the game
+    pub moves: Option<u32>,
+    pub time: Duration,
+}
+
+/// Time a player gets back, or doesn't lose, on every move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bonus {
+    None,
+    /// Added after every move
+    Fischer(Duration),
+    /// The time spent on a move is given back after it, up to this much
+    Bronstein(Duration),
+    /// The clock only starts running this long after the turn begins
+    SimpleDelay(Duration),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeControl {
+    /// Played one after the other. The last one repeats if it has a move count.
+    pub periods: Vec<Period>,
+    pub bonus: Bonus,
+}
+
+impl TimeControl {
+    pub fn sudden_death(time: Duration) -> Self {
+        Self {
+            periods: vec![Period { moves: None, time }],
+            bonus: Bonus::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeControlError(pub String);
+
+impl fmt::Display for TimeControlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bad time control \"{}\"", self.0)
+    }
+}
+
+fn minutes(text: &str) -> Option<Duration> {
+    text.parse::<f64>()
+        .ok()
+        .filter(|minutes| minutes.is_finite() && *minutes > 0.)
+        .map(|minutes| Duration::from_secs_f64(minutes * 60.))
+}
+
+fn seconds(text: &str) -> Option<Duration> {
+    text.parse::<f64>()
+        .ok()
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.)
+        .map(Duration::from_secs_f64)
+}
+
+/// Reads a time control. Periods are separated by colons, each one "minutes" or
+/// "moves/minutes", and the whole control may end with a bonus in seconds: "+s" for Fischer
+/// increment, "ds" for simple delay or "bs" for Bronstein delay.
+///
+/// "5" is five minutes sudden death, "3+2" three minutes with two seconds increment, "5d3"
+/// five minutes with a three second delay, and "40/90:30+30" 90 minutes for 40 moves, then
+/// 30 minutes for the rest, with 30 seconds increment from the first move.
+pub fn parse_time_control(spec: &str) -> Result<TimeControl, TimeControlError> {
+    let error = || TimeControlError(spec.to_string());
+
+    let (periods_text, bonus) = match spec.find(&['+', 'd', 'b'][..]) {
+        Some(index) => {
+            let time = seconds(&spec[index + 1..]).ok_or_else(error)?;
+            let bonus = match &spec[index..=index] {
+                "+" => Bonus::Fischer(time),
+                "d" => Bonus::SimpleDelay(time),
+                _ => Bonus::Bronstein(time),
+            };
+            (&spec[..index], bonus)
+        }
+        None => (spec, Bonus::None),
+    };
+
+    let mut periods = Vec::new();
+    for text in periods_text.split(':') {
+        let period = match text.find('/') {
+            Some(index) => Period {
+                moves: Some(
+                    text[..index]
+                        .parse()
+                        .ok()
+                        .filter(|moves| *moves > 0)
+                        .ok_or_else(error)?,
+                ),
+                time: minutes(&text[index + 1..]).ok_or_else(error)?,
+            },
+            None => Period {
+                moves: None,
+                time: minutes(text).ok_or_else(error)?,
+            },
+        };
+        periods.push(period);
+    }
+
+    // Only the last period may last for the rest of the game
+    if periods[..periods.len() - 1]
+        .iter()
+        .any(|period| period.moves.is_none())
+    {
+        return Err(error());
+    }
+    Ok(TimeControl { periods, bonus })
+}
+
+// Clock ======================================================================================== //
+/// Time left, period and moves made in it for both players
+#[derive(Debug, Clone, Copy)]
+struct Times {
+    remaining: [Duration; 2],
+    period: [usize; 2],
+    period_moves: [u32; 2],
+}
+
+/// Both players' time under a `TimeControl`
+#[derive(Debug, Clone)]
+pub struct Clock {
+    control: TimeControl,
+    times: Times,
+    /// Times at the start of every half move so far, the current one last
+    starts: Vec<Times>,
+    /// Starts of the half moves taken back, the most recent last
+    taken_back: Vec<Times>,
+    /// Time spent on the current move, for the delays
+    thinking: Duration,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        let times = Times {
+            remaining: [control.periods[0].time; 2],
+            period: [0; 2],
+            period_moves: [0; 2],
+        };
+        Self {
+            control,
+            times,
+            starts: vec![times],
+            taken_back: Vec::new(),
+            thinking: Duration::default(),
+        }
+    }
+
+    pub fn control(&self) -> &TimeControl {
+        &self.control
+    }
+
+    pub fn remaining(&self, color: PieceColor) -> Duration {
+        self.times.remaining[color.index()]
+    }
+
+    /// True once `color` has no time left
+    pub fn is_flagged(&self, color: PieceColor) -> bool {
+        self.remaining(color) == Duration::default()
+    }
+
+    /// Lets `elapsed` pass on the clock of `color`, whose turn it is
+    pub fn tick(&mut self, color: PieceColor, elapsed: Duration) {
+        let counted = match self.control.bonus {
+            Bonus::SimpleDelay(delay) => {
+                elapsed - elapsed.min(delay.checked_sub(self.thinking).unwrap_or_default())
+            }
+            _ => elapsed,
+        };
+        self.thinking += elapsed;
+
+        let remaining = &mut self.times.remaining[color.index()];
+        *remaining = remaining.checked_sub(counted).unwrap_or_default();
+    }
+
+    /// Stops the clock of `color` after its move: adds the bonus and starts the next period
+    /// once enough moves were made
+    pub fn press(&mut self, color: PieceColor) {
+        let thinking = std::mem::take(&mut self.thinking);
+        let i = color.index();
+        if !self.is_flagged(color) {
+            let times = &mut self.times;
+            match self.control.bonus {
+                Bonus::Fischer(increment) => times.remaining[i] += increment,
+                Bonus::Bronstein(delay) => times.remaining[i] += thinking.min(delay),
+                Bonus::None | Bonus::SimpleDelay(_) => {}
+            }
+
+            times.period_moves[i] += 1;
+            if self.control.periods[times.period[i]].moves == Some(times.period_moves[i]) {
+                times.period[i] = (times.period[i] + 1).min(self.control.periods.len() - 1);
+                times.period_moves[i] = 0;
+                times.remaining[i] += self.control.periods[times.period[i]].time;
+            }
+        }
+        self.starts.push(self.times);
+        self.taken_back.clear();
+    }
+
+    /// Sets the clock back or forward to the start of half move `ply`, after the game was
+    /// undone or redone to it. Only goes as far as the moves pressed and taken back.
+    pub fn go_to(&mut self, ply: usize) {
+        while self.starts.len() > ply + 1 {
+            let times = self.starts.pop().unwrap();
+            self.taken_back.push(times);
+        }
+        while self.starts.len() < ply + 1 {
+            match self.taken_back.pop() {
+                Some(times) => self.starts.push(times),
+                None => break,
+            }
+        }
+        self.times = *self.starts.last().unwrap();
+        self.thinking = Duration::default();
+    }
+}
+
+/// "1:05:00", "4:59" and, under ten seconds, "9.7"
+pub fn format_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else if seconds >= 10 {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    } else {
+        format!("{}.{}", seconds, time.subsec_millis() / 100)
+    }
+}
+
+// Systems ====================================================================================== //
+/// The clock of the game on the board, if it is timed
+#[derive(Default)]
+pub struct GameClock(pub Option<Clock>);
+
+/// Run the clock of the side to move, and end the game when it runs out
+fn run_clock(
+    time: Res<Time>,
+    turn: Res<PlayerTurn>,
+    replay: Res<Replay>,
+    mut clock: ResMut<GameClock>,
+    mut game: ResMut<Game>,
+    mut game_over_events: ResMut<Events<GameOver>>,
+) {
+    let clock = match clock.0.as_mut() {
+        Some(clock) => clock,
+        None => return,
+    };
+    if replay.is_active() || game.outcome().is_some() {
+        return;
+    }
+
+    clock.tick(turn.0, time.delta());
+    if clock.is_flagged(turn.0) {
+        if let Some(game_over) = game.time_out(turn.0) {
+            game_over_events.send(game_over);
+        }
+    }
+}
+
+pub struct ClockPlugin;
+impl Plugin for ClockPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<GameClock>()
+            .add_system(run_clock.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::parse_san;
+
+    fn secs(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn time_controls() {
+        let control = |spec: &str| parse_time_control(spec).unwrap();
+        assert_eq!(control("5"), TimeControl::sudden_death(secs(300)));
+        assert_eq!(control("0.5"), TimeControl::sudden_death(secs(30)));
+        assert_eq!(
+            control("3+2"),
+            TimeControl {
+                periods: vec![Period {
+                    moves: None,
+                    time: secs(180),
+                }],
+                bonus: Bonus::Fischer(secs(2)),
+            }
+        );
+        assert_eq!(control("5d3").bonus, Bonus::SimpleDelay(secs(3)));
+        assert_eq!(control("5b3").bonus, Bonus::Bronstein(secs(3)));
+        assert_eq!(
+            control("40/90:30+30"),
+            TimeControl {
+                periods: vec![
+                    Period {
+                        moves: Some(40),
+                        time: secs(90 * 60),
+                    },
+                    Period {
+                        moves: None,
+                        time: secs(30 * 60),
+                    },
+                ],
+                bonus: Bonus::Fischer(secs(30)),
+            }
+        );
+
+        for spec in ["", "0", "-5", "x", "5+", "5+x", "0/90", "40/", "30:40/90"].iter() {
+            assert_eq!(
+                parse_time_control(spec),
+                Err(TimeControlError(spec.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn increment_and_flag() {
+        let mut clock = Clock::new(parse_time_control("3+2").unwrap());
+        clock.tick(PieceColor::White, secs(10));
+        clock.press(PieceColor::White);
+        assert_eq!(clock.remaining(PieceColor::White), secs(172));
+        assert_eq!(clock.remaining(PieceColor::Black), secs(180));
+
+        clock.tick(PieceColor::Black, secs(200));
+        assert!(clock.is_flagged(PieceColor::Black));
+        assert_eq!(clock.remaining(PieceColor::Black), Duration::default());
+        // No increment once the flag has fallen
+        clock.press(PieceColor::Black);
+        assert!(clock.is_flagged(PieceColor::Black));
+    }
+
+    #[test]
+    fn delays() {
+        let mut simple = Clock::new(parse_time_control("5d3").unwrap());
+        simple.tick(PieceColor::White, secs(2));
+        assert_eq!(simple.remaining(PieceColor::White), secs(300));
+        simple.tick(PieceColor::White, secs(2));
+        assert_eq!(simple.remaining(PieceColor::White), secs(299));
+        // The delay starts over on every move
+        simple.press(PieceColor::White);
+        simple.tick(PieceColor::Black, secs(3));
+        assert_eq!(simple.remaining(PieceColor::Black), secs(300));
+
+        let mut bronstein = Clock::new(parse_time_control("5b3").unwrap());
+        bronstein.tick(PieceColor::White, secs(2));
+        assert_eq!(bronstein.remaining(PieceColor::White), secs(298));
+        bronstein.press(PieceColor::White);
+        assert_eq!(bronstein.remaining(PieceColor::White), secs(300));
+        bronstein.tick(PieceColor::Black, secs(5));
+        bronstein.press(PieceColor::Black);
+        assert_eq!(bronstein.remaining(PieceColor::Black), secs(298));
+    }
+
+    #[test]
+    fn periods() {
+        let mut clock = Clock::new(parse_time_control("2/10:5").unwrap());
+        for _ in 0..2 {
+            clock.tick(PieceColor::White, secs(60));
+            clock.press(PieceColor::White);
+        }
+        assert_eq!(clock.remaining(PieceColor::White), secs(480 + 300));
+        // The last period lasts for the rest of the game
+        for _ in 0..4 {
+            clock.press(PieceColor::White);
+        }
+        assert_eq!(clock.remaining(PieceColor::White), secs(780));
+
+        // A last period with a move count repeats
+        let mut clock = Clock::new(parse_time_control("2/10").unwrap());
+        for _ in 0..4 {
+            clock.press(PieceColor::White);
+        }
+        assert_eq!(clock.remaining(PieceColor::White), secs(1800));
+    }
+
+    #[test]
+    fn undo_after_timeout() {
+        let mut game = Game::default();
+        let mut clock = Clock::new(parse_time_control("3+2").unwrap());
+        let mut play = |san, seconds| {
+            let color = game.turn();
+            let mv = parse_san(game.position(), san).unwrap();
+            clock.tick(color, secs(seconds));
+            game.make_move(mv).unwrap();
+            clock.press(color);
+        };
+        play("e4", 10);
+        play("e5", 20);
+        clock.tick(PieceColor::White, secs(200));
+        assert!(game.time_out(PieceColor::White).is_some());
+
+        // Taking back Black's move gives White the time it had back
+        game.undo();
+        clock.go_to(game.moves().len());
+        assert_eq!(game.outcome(), None);
+        assert!(!clock.is_flagged(PieceColor::White));
+        assert_eq!(clock.remaining(PieceColor::White), secs(172));
+        assert_eq!(clock.remaining(PieceColor::Black), secs(180));
+
+        game.undo();
+        clock.go_to(game.moves().len());
+        assert_eq!(clock.remaining(PieceColor::White), secs(180));
+
+        // Redo brings back the times after each move, bonus included, once
+        game.redo();
+        game.redo();
+        clock.go_to(game.moves().len());
+        assert_eq!(clock.remaining(PieceColor::White), secs(172));
+        assert_eq!(clock.remaining(PieceColor::Black), secs(162));
+
+        // A new move drops the moves taken back
+        game.undo();
+        clock.go_to(game.moves().len());
+        clock.press(PieceColor::Black);
+        clock.go_to(3);
+        assert_eq!(clock.remaining(PieceColor::Black), secs(182));
+    }
+}
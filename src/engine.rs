@@ -0,0 +1,533 @@
+//! The built-in opponent: iterative deepening alpha-beta with principal variation search,
+//! quiescence search and a transposition table, ordering moves by the table's best move,
+//! MVV-LVA and killer moves. Works on a headless `Position`, so it knows nothing about bevy.
+
+use crate::game::{Move, PieceColor, PieceType, Position};
+use crate::transposition::{score_from_table, Bound, TranspositionTable};
+use crate::zobrist::ZobristKey;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Score for mating right now, mates further away score a little less
+pub const MATE: i32 = 30_000;
+const INFINITY: i32 = 32_000;
+
+/// Deepest the search ever looks, extensions and quiescence included
+const MAX_PLY: usize = 128;
+
+/// How many nodes to search between looks at the clock, a power of two
+const NODES_BETWEEN_TIME_CHECKS: u64 = 1024;
+
+/// True if `score` announces a forced mate for either side
+pub fn is_mate_score(score: i32) -> bool {
+    score.abs() >= MATE - MAX_PLY as i32
+}
+
+// Evaluation =================================================================================== //
+pub fn piece_value(piece_type: PieceType) -> i32 {
+    match piece_type {
+        PieceType::Pawn => 100,
+        PieceType::Knight => 320,
+        PieceType::Bishop => 330,
+        PieceType::Rook => 500,
+        PieceType::Queen => 900,
+        PieceType::King => 0,
+    }
+}
+
+// Piece-square tables from White's side, the 8th rank first as on a diagram
+#[rustfmt::skip]
+const PAWN_TABLE: [[i32; 8]; 8] = [
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+    [ 50,  50,  50,  50,  50,  50,  50,  50],
+    [ 10,  10,  20,  30,  30,  20,  10,  10],
+    [  5,   5,  10,  25,  25,  10,   5,   5],
+    [  0,   0,   0,  20,  20,   0,   0,   0],
+    [  5,  -5, -10,   0,   0, -10,  -5,   5],
+    [  5,  10,  10, -20, -20,  10,  10,   5],
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+];
+
+#[rustfmt::skip]
+const KNIGHT_TABLE: [[i32; 8]; 8] = [
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+    [-40, -20,   0,   0,   0,   0, -20, -40],
+    [-30,   0,  10,  15,  15,  10,   0, -30],
+    [-30,   5,  15,  20,  20,  15,   5, -30],
+    [-30,   0,  15,  20,  20,  15,   0, -30],
+    [-30,   5,  10,  15,  15,  10,   5, -30],
+    [-40, -20,   0,   5,   5,   0, -20, -40],
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+];
+
+#[rustfmt::skip]
+const BISHOP_TABLE: [[i32; 8]; 8] = [
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+    [-10,   0,   0,   0,   0,   0,   0, -10],
+    [-10,   0,   5,  10,  10,   5,   0, -10],
+    [-10,   5,   5,  10,  10,   5,   5, -10],
+    [-10,   0,  10,  10,  10,  10,   0, -10],
+    [-10,  10,  10,  10,  10,  10,  10, -10],
+    [-10,   5,   0,   0,   0,   0,   5, -10],
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+];
+
+#[rustfmt::skip]
+const ROOK_TABLE: [[i32; 8]; 8] = [
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+    [  5,  10,  10,  10,  10,  10,  10,   5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [  0,   0,   0,   5,   5,   0,   0,   0],
+];
+
+#[rustfmt::skip]
+const QUEEN_TABLE: [[i32; 8]; 8] = [
+    [-20, -10, -10,  -5,  -5, -10, -10, -20],
+    [-10,   0,   0,   0,   0,   0,   0, -10],
+    [-10,   0,   5,   5,   5,   5,   0, -10],
+    [ -5,   0,   5,   5,   5,   5,   0,  -5],
+    [  0,   0,   5,   5,   5,   5,   0,  -5],
+    [-10,   5,   5,   5,   5,   5,   0, -10],
+    [-10,   0,   5,   0,   0,   0,   0, -10],
+    [-20, -10, -10,  -5,  -5, -10, -10, -20],
+];
+
+#[rustfmt::skip]
+const KING_MIDDLEGAME_TABLE: [[i32; 8]; 8] = [
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-20, -30, -30, -40, -40, -30, -30, -20],
+    [-10, -20, -20, -20, -20, -20, -20, -10],
+    [ 20,  20,   0,   0,   0,   0,  20,  20],
+    [ 20,  30,  10,   0,   0,  10,  30,  20],
+];
+
+#[rustfmt::skip]
+const KING_ENDGAME_TABLE: [[i32; 8]; 8] = [
+    [-50, -40, -30, -20, -20, -30, -40, -50],
+    [-30, -20, -10,   0,   0, -10, -20, -30],
+    [-30, -10,  20,  30,  30,  20, -10, -30],
+    [-30, -10,  30,  40,  40,  30, -10, -30],
+    [-30, -10,  30,  40,  40,  30, -10, -30],
+    [-30, -10,  20,  30,  30,  20, -10, -30],
+    [-30, -30,   0,   0,   0,   0, -30, -30],
+    [-50, -30, -30, -30, -30, -30, -30, -50],
+];
+
+/// Non-pawn material on the board at or below which kings head for the center
+const ENDGAME_MATERIAL: i32 = 1300;
+
+/// Material and piece placement, in centipawns from the side to move's point of view
+pub fn evaluate(position: &Position) -> i32 {
+    let endgame = position
+        .pieces()
+        .filter(|(_, piece)| piece.piece_type != PieceType::Pawn)
+        .map(|(_, piece)| piece_value(piece.piece_type))
+        .sum::<i32>()
+        <= ENDGAME_MATERIAL;
+
+    let mut score = 0;
+    for ((x, y), piece) in position.pieces() {
+        let table = match piece.piece_type {
+            PieceType::Pawn => &PAWN_TABLE,
+            PieceType::Knight => &KNIGHT_TABLE,
+            PieceType::Bishop => &BISHOP_TABLE,
+            PieceType::Rook => &ROOK_TABLE,
+            PieceType::Queen => &QUEEN_TABLE,
+            PieceType::King if endgame => &KING_ENDGAME_TABLE,
+            PieceType::King => &KING_MIDDLEGAME_TABLE,
+        };
+        // The tables list the 8th rank first, which is Black's first rank
+        let row = match piece.color {
+            PieceColor::White => 7 - x as usize,
+            PieceColor::Black => x as usize,
+        };
+        let value = piece_value(piece.piece_type) + table[row][y as usize];
+        if piece.color == position.turn() {
+            score += value;
+        } else {
+            score -= value;
+        }
+    }
+    score
+}
+
+// Search ======================================================================================= //
+/// When to stop searching. With none set the search only stops at `MAX_PLY`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchLimits {
+    pub depth: Option<u32>,
+    pub movetime: Option<Duration>,
+    pub nodes: Option<u64>,
+}
+
+/// Outcome of a finished iteration
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchInfo {
+    pub depth: u32,
+    /// Centipawns from the side to move's point of view, or a mate score
+    pub score: i32,
+    pub nodes: u64,
+    pub time: Duration,
+    /// Principal variation, the best move first
+    pub pv: Vec<Move>,
+}
+
+impl SearchInfo {
+    pub fn best_move(&self) -> Option<Move> {
+        self.pv.first().copied()
+    }
+}
+
+pub struct Searcher {
+    /// Shared with the searches before and after this one
+    table: Arc<Mutex<TranspositionTable>>,
+    /// Keys of the game's positions before the root, then of the positions on the way from
+    /// the root to the node searched, to spot repetitions
+    keys: Vec<ZobristKey>,
+    /// Two quiet moves per ply that caused a beta cutoff, tried early in sibling nodes
+    killers: Vec<[Option<Move>; 2]>,
+    /// Principal variation of the last finished iteration, searched first in the next one
+    previous_pv: Vec<Move>,
+    nodes: u64,
+    max_nodes: Option<u64>,
+    deadline: Option<Instant>,
+    /// Set from another thread to cancel the search
+    stop: Arc<AtomicBool>,
+    aborted: bool,
+}
+
+impl Default for Searcher {
+    fn default() -> Self {
+        Self::with_table(Arc::new(Mutex::new(TranspositionTable::default())))
+    }
+}
+
+impl Searcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A searcher using `table`, so what it learns is there for later searches
+    pub fn with_table(table: Arc<Mutex<TranspositionTable>>) -> Self {
+        Self {
+            table,
+            keys: Vec::new(),
+            killers: vec![[None; 2]; MAX_PLY],
+            previous_pv: Vec::new(),
+            nodes: 0,
+            max_nodes: None,
+            deadline: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            aborted: false,
+        }
+    }
+
+    /// Setting the returned flag stops the search as soon as it notices, even during the
+    /// first iteration
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Searches `position` deeper and deeper until `limits` say stop, calling `report` after
+    /// every finished iteration. `history` holds the keys of the positions the game went
+    /// through before, oldest first, so the search knows which moves repeat one. Unless
+    /// stopped, the first iteration always finishes, so there is a best move whenever the side
+    /// to move has one.
+    pub fn search<F: FnMut(&SearchInfo)>(
+        &mut self,
+        position: &Position,
+        history: &[ZobristKey],
+        limits: SearchLimits,
+        mut report: F,
+    ) -> SearchInfo {
+        let start = Instant::now();
+        self.keys = history.to_vec();
+        self.killers = vec![[None; 2]; MAX_PLY];
+        self.previous_pv.clear();
+        self.nodes = 0;
+        self.max_nodes = limits.nodes;
+        self.deadline = limits.movetime.map(|movetime| start + movetime);
+        self.aborted = false;
+        self.table.lock().unwrap().new_search();
+
+        // Moves are made and taken back on this one copy as the search goes
+        let mut position = position.clone();
+        let max_depth = limits.depth.unwrap_or(MAX_PLY as u32).max(1);
+        let mut best = SearchInfo::default();
+        for depth in 1..=max_depth {
+            let mut pv = Vec::new();
+            let score = self.alpha_beta(&mut position, depth, 0, -INFINITY, INFINITY, &mut pv);
+            if self.aborted {
+                break;
+            }
+
+            best = SearchInfo {
+                depth,
+                score,
+                nodes: self.nodes,
+                time: start.elapsed(),
+                pv: pv.clone(),
+            };
+            report(&best);
+            self.previous_pv = pv;
+
+            // Nothing to think about, or a mate was found that deeper searches can't improve
+            if best.pv.len() < depth as usize && (best.pv.is_empty() || is_mate_score(score)) {
+                break;
+            }
+        }
+        best.nodes = self.nodes;
+        best.time = start.elapsed();
+        best
+    }
+
+    /// Gives up when stopped, or once out of time or nodes but never during the first
+    /// iteration
+    fn should_stop(&mut self) -> bool {
+        if self.aborted {
+            return true;
+        }
+        let first_iteration = self.previous_pv.is_empty();
+        let out_of_nodes = matches!(self.max_nodes, Some(max_nodes) if self.nodes > max_nodes);
+        if !first_iteration && out_of_nodes {
+            self.aborted = true;
+        } else if self.nodes & (NODES_BETWEEN_TIME_CHECKS - 1) == 0 {
+            let out_of_time = !first_iteration
+                && matches!(self.deadline, Some(deadline) if Instant::now() >= deadline);
+            self.aborted = out_of_time || self.stop.load(Ordering::Relaxed);
+        }
+        self.aborted
+    }
+
+    fn alpha_beta(
+        &mut self,
+        position: &mut Position,
+        depth: u32,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+        pv: &mut Vec<Move>,
+    ) -> i32 {
+        pv.clear();
+        self.nodes += 1;
+        if self.should_stop() {
+            return 0;
+        }
+        let key = position.key();
+        if ply > 0
+            && (position.halfmove_clock() >= 100
+                || position.is_insufficient_material()
+                || self.is_repetition(key, position.halfmove_clock()))
+        {
+            return 0;
+        }
+
+        // Look one move further when in check, so mates and escapes aren't cut off
+        let in_check = position.is_in_check(position.turn());
+        let depth = if in_check { depth + 1 } else { depth };
+        if depth == 0 || ply >= MAX_PLY - 1 {
+            return self.quiescence(position, ply, alpha, beta);
+        }
+
+        // Outside the principal variation, a deep enough earlier search may already tell
+        // enough about this position. On it the search goes on so the whole line is known.
+        let entry = self.table.lock().unwrap().probe(key);
+        let table_move = entry.and_then(|entry| entry.best_move);
+        if let Some(entry) = entry {
+            let score = score_from_table(i32::from(entry.score), ply);
+            if beta - alpha == 1 && u32::from(entry.depth) >= depth {
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return beta,
+                    Bound::Upper if score <= alpha => return alpha,
+                    _ => {}
+                }
+            }
+        }
+
+        let mut moves = position.legal_moves();
+        if moves.is_empty() {
+            return if in_check { -MATE + ply as i32 } else { 0 };
+        }
+        self.order_moves(position, &mut moves, ply, table_move);
+
+        let original_alpha = alpha;
+        let mut line = Vec::new();
+        self.keys.push(key);
+        for (i, mv) in moves.iter().enumerate() {
+            let effects = position.apply_move(*mv);
+
+            // Principal variation search: the first move gets the full window, the others
+            // only need to prove they are no better, and are searched again if they are
+            let score = if i == 0 {
+                -self.alpha_beta(position, depth - 1, ply + 1, -beta, -alpha, &mut line)
+            } else {
+                let score =
+                    -self.alpha_beta(position, depth - 1, ply + 1, -alpha - 1, -alpha, &mut line);
+                if score > alpha && score < beta {
+                    -self.alpha_beta(position, depth - 1, ply + 1, -beta, -alpha, &mut line)
+                } else {
+                    score
+                }
+            };
+            position.unmake_move(*mv, &effects);
+            if self.aborted {
+                self.keys.pop();
+                return 0;
+            }
+
+            if score > alpha {
+                alpha = score;
+                pv.clear();
+                pv.push(*mv);
+                pv.extend_from_slice(&line);
+                if score >= beta {
+                    if !position.is_capture(*mv) && mv.promotion.is_none() {
+                        self.store_killer(ply, *mv);
+                    }
+                    self.keys.pop();
+                    self.table.lock().unwrap().store(
+                        key,
+                        depth,
+                        Bound::Lower,
+                        beta,
+                        Some(*mv),
+                        ply,
+                    );
+                    return beta;
+                }
+            }
+        }
+        self.keys.pop();
+
+        let bound = if alpha > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.table
+            .lock()
+            .unwrap()
+            .store(key, depth, bound, alpha, pv.first().copied(), ply);
+        alpha
+    }
+
+    /// True if the position with `key` occurred before with the same side to move, and could
+    /// still be reached since the last capture or pawn move. Going back there is a draw unless
+    /// one side wants to, so the search scores it as one the first time around.
+    fn is_repetition(&self, key: ZobristKey, halfmove_clock: u32) -> bool {
+        self.keys
+            .iter()
+            .rev()
+            .take(halfmove_clock as usize)
+            .skip(1)
+            .step_by(2)
+            .any(|earlier| *earlier == key)
+    }
+
+    /// Plays out captures and promotions until the position is quiet, so the evaluation never
+    /// stops in the middle of an exchange
+    fn quiescence(
+        &mut self,
+        position: &mut Position,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        self.nodes += 1;
+        if self.should_stop() {
+            return 0;
+        }
+
+        // Standing pat: the side to move doesn't have to take anything
+        let stand_pat = evaluate(position);
+        if stand_pat >= beta {
+            return beta;
+        }
+        alpha = alpha.max(stand_pat);
+        if ply >= MAX_PLY - 1 {
+            return alpha;
+        }
+
+        let mut moves: Vec<Move> = position
+            .legal_moves()
+            .into_iter()
+            .filter(|mv| position.is_capture(*mv) || mv.promotion.is_some())
+            .collect();
+        moves.sort_by_key(|mv| -mvv_lva(position, *mv));
+
+        for mv in moves {
+            let effects = position.apply_move(mv);
+            let score = -self.quiescence(position, ply + 1, -beta, -alpha);
+            position.unmake_move(mv, &effects);
+            if self.aborted {
+                return 0;
+            }
+            if score >= beta {
+                return beta;
+            }
+            alpha = alpha.max(score);
+        }
+        alpha
+    }
+
+    /// The best move the transposition table knows first, then the move of the last principal
+    /// variation, then captures by MVV-LVA, then killer moves, then the rest
+    fn order_moves(
+        &self,
+        position: &Position,
+        moves: &mut [Move],
+        ply: usize,
+        table_move: Option<Move>,
+    ) {
+        let pv_move = self.previous_pv.get(ply).copied();
+        let killers = self.killers[ply];
+        moves.sort_by_key(|mv| {
+            let score = if Some(*mv) == table_move {
+                2_000_000
+            } else if Some(*mv) == pv_move {
+                1_000_000
+            } else if position.is_capture(*mv) || mv.promotion.is_some() {
+                100_000 + mvv_lva(position, *mv)
+            } else if Some(*mv) == killers[0] {
+                90_000
+            } else if Some(*mv) == killers[1] {
+                80_000
+            } else {
+                0
+            };
+            -score
+        });
+    }
+
+    fn store_killer(&mut self, ply: usize, mv: Move) {
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(mv) {
+            killers[1] = killers[0];
+            killers[0] = Some(mv);
+        }
+    }
+}
+
+/// Most valuable victim, least valuable attacker: taking a queen with a pawn comes first.
+/// Promotions count the piece they promote to.
+fn mvv_lva(position: &Position, mv: Move) -> i32 {
+    let victim = match position.piece_at(mv.to) {
+        Some(piece) => piece_value(piece.piece_type),
+        None if position.is_capture(mv) => piece_value(PieceType::Pawn),
+        None => 0,
+    };
+    let attacker = position
+        .piece_at(mv.from)
+        .map_or(0, |piece| piece_value(piece.piece_type));
+    let promotion = mv.promotion.map_or(0, piece_value);
+    10 * (victim + promotion) - attacker
+}
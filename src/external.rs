@@ -0,0 +1,163 @@
+use crate::board::{PlayMove, ReviewPly};
+use crate::clock::GameClock;
+use crate::engine::SearchLimits;
+use crate::game::{Game, Position};
+use crate::notation::parse_uci;
+use crate::opponent::EngineOpponent;
+use crate::replay::Replay;
+use crate::uci_client::{EngineInfo, EngineMessage, UciClient};
+use bevy::prelude::*;
+
+/// A UCI engine from another program, set up in the config file or on the command line. It
+/// plays the side `EngineOpponent` gives it, or analyses the position on the board if that is
+/// none.
+#[derive(Default)]
+pub struct ExternalEngine {
+    client: Option<UciClient>,
+    /// Position the engine was last given or told to stop on
+    searched: Option<Position>,
+}
+
+impl ExternalEngine {
+    pub fn new(client: UciClient) -> Self {
+        Self {
+            client: Some(client),
+            searched: None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Tells the engine a new game starts, so it doesn't carry anything over from the last one
+    pub fn new_game(&mut self) {
+        if let Some(Err(error)) = self.client.as_mut().map(UciClient::new_game) {
+            give_up(self, error);
+        }
+        // Stopped for the new game, so the starting position is searched again
+        self.searched = None;
+    }
+}
+
+/// What the external engine last said about the position it is searching
+#[derive(Default)]
+pub struct ExternalAnalysis {
+    pub engine_name: String,
+    /// Position searched, to make sense of the principal variation
+    pub position: Position,
+    pub info: Option<EngineInfo>,
+}
+
+/// Drop the engine after telling what went wrong with it, the board goes on without
+fn give_up(engine: &mut ExternalEngine, error: impl std::fmt::Display) {
+    if let Some(client) = engine.client.take() {
+        eprintln!("{}: {}", client.name(), error);
+    }
+}
+
+/// Whenever the position on the board changes, have the engine search it for its move when it
+/// plays the side to move, or analyse it when it plays no side
+fn start_external_search(
+    game: Res<Game>,
+    review: Res<ReviewPly>,
+    replay: Res<Replay>,
+    opponent: Res<EngineOpponent>,
+    clock: Res<GameClock>,
+    mut engine: ResMut<ExternalEngine>,
+    mut analysis: ResMut<ExternalAnalysis>,
+) {
+    let engine = &mut *engine;
+    let client = match engine.client.as_mut() {
+        Some(client) => client,
+        None => return,
+    };
+    let ply = review.0.unwrap_or_else(|| game.moves().len());
+    let shown = game.position_at(ply);
+    if engine.searched.as_ref() == Some(shown) {
+        return;
+    }
+    engine.searched = Some(shown.clone());
+
+    let limits = if opponent.color.is_none() {
+        // Analysing, until the position changes
+        SearchLimits::default()
+    } else if replay.is_active()
+        || review.0.is_some()
+        || !opponent.plays(game.turn())
+        || game.outcome().is_some()
+    {
+        if let Err(error) = client.stop() {
+            give_up(engine, error);
+        }
+        return;
+    } else {
+        opponent.limits(&clock)
+    };
+
+    let result = client.go(game.starting_position(), &game.moves()[..ply], limits);
+    *analysis = ExternalAnalysis {
+        engine_name: client.name().to_string(),
+        position: shown.clone(),
+        info: None,
+    };
+    if let Err(error) = result {
+        give_up(engine, error);
+    }
+}
+
+/// Keep up with what the engine says, and play its move like a human move would be played
+fn read_external_engine(
+    game: Res<Game>,
+    review: Res<ReviewPly>,
+    replay: Res<Replay>,
+    opponent: Res<EngineOpponent>,
+    mut engine: ResMut<ExternalEngine>,
+    mut analysis: ResMut<ExternalAnalysis>,
+    mut play_move_events: ResMut<Events<PlayMove>>,
+) {
+    loop {
+        let client = match engine.client.as_mut() {
+            Some(client) => client,
+            None => return,
+        };
+        let message = match client.try_message() {
+            Ok(Some(message)) => message,
+            Ok(None) => return,
+            Err(error) => {
+                give_up(&mut engine, error);
+                return;
+            }
+        };
+
+        match message {
+            // Lines such as "info currmove" don't replace the last score and line
+            EngineMessage::Info(info) if info.score.is_some() => analysis.info = Some(info),
+            EngineMessage::Info(_) => {}
+            EngineMessage::BestMove(best_move) => {
+                // Searches for other positions are stopped before their answer comes, so
+                // this one is about the game's position
+                if replay.is_active() || review.0.is_some() || !opponent.plays(game.turn()) {
+                    continue;
+                }
+                match best_move.and_then(|text| parse_uci(game.position(), &text)) {
+                    Some(mv) => play_move_events.send(PlayMove(mv)),
+                    None => {
+                        give_up(&mut engine, "no legal best move");
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct ExternalEnginePlugin;
+impl Plugin for ExternalEnginePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<ExternalEngine>()
+            .init_resource::<ExternalAnalysis>()
+            .add_system(start_external_search.system())
+            .add_system(read_external_engine.system());
+    }
+}
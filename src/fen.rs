@@ -0,0 +1,334 @@
+//! Forsyth-Edwards Notation for `Position`s: all six fields, placement, side to move,
+//! castling, en passant and both clocks.
+
+use crate::game::{
+    parse_square, square_name, BoardPiece, CastlingRights, PieceColor, PieceType, Position,
+};
+use std::fmt;
+
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FenError {
+    /// Needs the placement, side to move, castling and en passant fields; the clocks are
+    /// optional
+    WrongFieldCount(usize),
+    BadPlacement(String),
+    BadTurn(String),
+    BadCastling(String),
+    BadEnPassant(String),
+    BadClock(String),
+    /// Each side needs exactly one king
+    BadKings,
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FenError::WrongFieldCount(count) => {
+                write!(f, "expected 4 to 6 fields, found {}", count)
+            }
+            FenError::BadPlacement(field) => write!(f, "bad piece placement \"{}\"", field),
+            FenError::BadTurn(field) => write!(f, "bad side to move \"{}\"", field),
+            FenError::BadCastling(field) => write!(f, "bad castling rights \"{}\"", field),
+            FenError::BadEnPassant(field) => write!(f, "bad en passant square \"{}\"", field),
+            FenError::BadClock(field) => write!(f, "bad move clock \"{}\"", field),
+            FenError::BadKings => write!(f, "each side needs exactly one king"),
+        }
+    }
+}
+
+fn piece_char(piece: BoardPiece) -> char {
+    let c = match piece.piece_type {
+        PieceType::King => 'k',
+        PieceType::Queen => 'q',
+        PieceType::Bishop => 'b',
+        PieceType::Knight => 'n',
+        PieceType::Rook => 'r',
+        PieceType::Pawn => 'p',
+    };
+    match piece.color {
+        PieceColor::White => c.to_ascii_uppercase(),
+        PieceColor::Black => c,
+    }
+}
+
+fn char_piece(c: char) -> Option<BoardPiece> {
+    let piece_type = match c.to_ascii_lowercase() {
+        'k' => PieceType::King,
+        'q' => PieceType::Queen,
+        'b' => PieceType::Bishop,
+        'n' => PieceType::Knight,
+        'r' => PieceType::Rook,
+        'p' => PieceType::Pawn,
+        _ => return None,
+    };
+    let color = if c.is_ascii_uppercase() {
+        PieceColor::White
+    } else {
+        PieceColor::Black
+    };
+    Some(BoardPiece::new(color, piece_type))
+}
+
+/// Reads a position from FEN
+pub fn parse_fen(fen: &str) -> Result<Position, FenError> {
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+    if fields.len() < 4 || fields.len() > 6 {
+        return Err(FenError::WrongFieldCount(fields.len()));
+    }
+    let mut position = Position::empty();
+
+    // Placement, from the 8th rank down to the 1st
+    let bad_placement = || FenError::BadPlacement(fields[0].to_string());
+    let ranks: Vec<&str> = fields[0].split('/').collect();
+    if ranks.len() != 8 {
+        return Err(bad_placement());
+    }
+    for (i, rank) in ranks.iter().enumerate() {
+        let x = 7 - i as u8;
+        let mut y = 0u8;
+        for c in rank.chars() {
+            if let Some(empty) = c.to_digit(10) {
+                if empty == 0 {
+                    return Err(bad_placement());
+                }
+                y += empty as u8;
+            } else {
+                let piece = char_piece(c).ok_or_else(bad_placement)?;
+                // Pawns never stand on the first or last rank
+                if y > 7 || (piece.piece_type == PieceType::Pawn && (x == 0 || x == 7)) {
+                    return Err(bad_placement());
+                }
+                position.set_piece((x, y), Some(piece));
+                y += 1;
+            }
+            if y > 8 {
+                return Err(bad_placement());
+            }
+        }
+        if y != 8 {
+            return Err(bad_placement());
+        }
+    }
+    for color in [PieceColor::White, PieceColor::Black].iter() {
+        let kings = position
+            .pieces()
+            .filter(|(_, piece)| *piece == BoardPiece::new(*color, PieceType::King))
+            .count();
+        if kings != 1 {
+            return Err(FenError::BadKings);
+        }
+    }
+
+    // Side to move
+    position.set_turn(match fields[1] {
+        "w" => PieceColor::White,
+        "b" => PieceColor::Black,
+        other => return Err(FenError::BadTurn(other.to_string())),
+    });
+
+    // Castling
+    let mut castling = CastlingRights::default();
+    if fields[2] != "-" {
+        for c in fields[2].chars() {
+            match c {
+                'K' => castling.white_kingside = true,
+                'Q' => castling.white_queenside = true,
+                'k' => castling.black_kingside = true,
+                'q' => castling.black_queenside = true,
+                _ => return Err(FenError::BadCastling(fields[2].to_string())),
+            }
+        }
+    }
+    position.set_castling(castling);
+
+    // En passant, behind a pawn of the other side that could just have made a double step
+    if fields[3] != "-" {
+        let (rank, pawn_rank) = match position.turn() {
+            PieceColor::White => (5, 4),
+            PieceColor::Black => (2, 3),
+        };
+        let pawn = BoardPiece::new(position.turn().opposite(), PieceType::Pawn);
+        match parse_square(fields[3]) {
+            Some(square)
+                if square.0 == rank && position.piece_at((pawn_rank, square.1)) == Some(pawn) =>
+            {
+                position.set_en_passant(Some(square))
+            }
+            _ => return Err(FenError::BadEnPassant(fields[3].to_string())),
+        }
+    }
+
+    // Clocks
+    if let Some(field) = fields.get(4) {
+        let clock = field
+            .parse()
+            .map_err(|_| FenError::BadClock(field.to_string()))?;
+        position.set_halfmove_clock(clock);
+    }
+    if let Some(field) = fields.get(5) {
+        match field.parse() {
+            Ok(number) if number > 0 => position.set_fullmove_number(number),
+            _ => return Err(FenError::BadClock(field.to_string())),
+        }
+    }
+
+    Ok(position)
+}
+
+/// Writes a position as FEN
+pub fn to_fen(position: &Position) -> String {
+    let mut placement = String::new();
+    for x in (0..8).rev() {
+        let mut empty = 0;
+        for y in 0..8 {
+            match position.piece_at((x, y)) {
+                Some(piece) => {
+                    if empty > 0 {
+                        placement.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    placement.push(piece_char(piece));
+                }
+                None => empty += 1,
+            }
+        }
+        if empty > 0 {
+            placement.push_str(&empty.to_string());
+        }
+        if x > 0 {
+            placement.push('/');
+        }
+    }
+
+    let turn = match position.turn() {
+        PieceColor::White => "w",
+        PieceColor::Black => "b",
+    };
+
+    let rights = position.castling();
+    let mut castling = String::new();
+    for (allowed, c) in [
+        (rights.white_kingside, 'K'),
+        (rights.white_queenside, 'Q'),
+        (rights.black_kingside, 'k'),
+        (rights.black_queenside, 'q'),
+    ]
+    .iter()
+    {
+        if *allowed {
+            castling.push(*c);
+        }
+    }
+    if castling.is_empty() {
+        castling.push('-');
+    }
+
+    let en_passant = position
+        .en_passant()
+        .map(square_name)
+        .unwrap_or_else(|| "-".to_string());
+
+    format!(
+        "{} {} {} {} {} {}",
+        placement,
+        turn,
+        castling,
+        en_passant,
+        position.halfmove_clock(),
+        position.fullmove_number()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for fen in [
+            STARTING_FEN,
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2",
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b Kq - 37 64",
+        ]
+        .iter()
+        {
+            assert_eq!(to_fen(&parse_fen(fen).unwrap()), *fen);
+        }
+
+        // The clocks may be left out
+        assert_eq!(
+            to_fen(&parse_fen("4k3/8/8/8/8/8/8/4K3 b - -").unwrap()),
+            "4k3/8/8/8/8/8/8/4K3 b - - 0 1"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_fields() {
+        let error = |fen: &str| parse_fen(fen).unwrap_err();
+        assert_eq!(error("4k3/8/8/8 w"), FenError::WrongFieldCount(2));
+
+        let bad_placement = |fen: &str| match error(fen) {
+            FenError::BadPlacement(_) => {}
+            other => panic!("{} gave {:?}", fen, other),
+        };
+        bad_placement("4k3/8/8/8/8/8/8 w - - 0 1");
+        bad_placement("4k3/8/8/8/8/8/8/4K4 w - - 0 1");
+        bad_placement("4k3/8/8/8/8/8/8/4K2 w - - 0 1");
+        bad_placement("4k3/8/8/8/8/8/8/04K3 w - - 0 1");
+        bad_placement("4k3/8/8/8/8/8/8/4X3 w - - 0 1");
+        bad_placement("4k2P/8/8/8/8/8/8/4K3 w - - 0 1");
+        bad_placement("4k3/8/8/8/8/8/8/p3K3 w - - 0 1");
+
+        assert_eq!(error("8/8/8/8/8/8/8/4K3 w - - 0 1"), FenError::BadKings);
+        assert_eq!(error("4k3/8/8/8/8/8/8/3KK3 w - - 0 1"), FenError::BadKings);
+        assert_eq!(
+            error("4k3/8/8/8/8/8/8/4K3 x - - 0 1"),
+            FenError::BadTurn("x".to_string())
+        );
+        assert_eq!(
+            error("4k3/8/8/8/8/8/8/4K3 w KX - 0 1"),
+            FenError::BadCastling("KX".to_string())
+        );
+        assert_eq!(
+            error("4k3/8/8/8/8/8/8/4K3 w - - -1 1"),
+            FenError::BadClock("-1".to_string())
+        );
+        assert_eq!(
+            error("4k3/8/8/8/8/8/8/4K3 w - - 0 0"),
+            FenError::BadClock("0".to_string())
+        );
+    }
+
+    #[test]
+    fn en_passant_needs_the_pawn_that_double_stepped() {
+        assert!(parse_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").is_ok());
+        assert!(parse_fen("4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1").is_ok());
+
+        for fen in [
+            // Rank 3 with white to move, which would let white take its own pawn
+            "K6k/8/8/8/8/8/4P3/8 w - e3 0 1",
+            "4k3/8/8/8/4P3/8/8/4K3 w - e3 0 1",
+            // No black pawn in front of the square
+            "4k3/8/8/8/8/8/8/4K3 w - d6 0 1",
+            "4k3/8/8/3P4/8/8/8/4K3 w - d6 0 1",
+            "4k3/8/8/3pP3/8/8/8/4K3 b - d6 0 1",
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d4 0 1",
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d9 0 1",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                parse_fen(fen),
+                Err(FenError::BadEnPassant(
+                    fen.split(' ').nth(3).unwrap().to_string()
+                )),
+                "{}",
+                fen
+            );
+        }
+    }
+}
@@ -0,0 +1,1330 @@
+//! Headless chess rules.
+//!
+//! Nothing in here depends on bevy: `Position` owns the board and the side to move, and
+//! `Game` applies moves to it. The ECS pieces in `pieces.rs` and `board.rs` only mirror
+//! what happens here.
+
+use crate::bitboard::{
+    bishop_attacks, king_attacks, knight_attacks, pawn_attacks, queen_attacks, rook_attacks,
+    square_bit, squares, Bitboard,
+};
+use crate::zobrist::{castling_key, en_passant_key, piece_key, turn_key, ZobristKey};
+use std::fmt;
+
+// Piece attributes ============================================================================= //
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PieceColor {
+    White,
+    Black,
+}
+
+impl PieceColor {
+    pub const ALL: [PieceColor; 2] = [PieceColor::White, PieceColor::Black];
+
+    /// 0 for White, 1 for Black, for tables indexed by color
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn opposite(self) -> Self {
+        match self {
+            PieceColor::White => PieceColor::Black,
+            PieceColor::Black => PieceColor::White,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PieceType {
+    King,
+    Queen,
+    Bishop,
+    Knight,
+    Rook,
+    Pawn,
+}
+
+impl PieceType {
+    pub const ALL: [PieceType; 6] = [
+        PieceType::King,
+        PieceType::Queen,
+        PieceType::Bishop,
+        PieceType::Knight,
+        PieceType::Rook,
+        PieceType::Pawn,
+    ];
+
+    /// Position in `ALL`, for tables indexed by piece type
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// A piece as the rules see it: no position, no entity, just what stands on a square
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BoardPiece {
+    pub color: PieceColor,
+    pub piece_type: PieceType,
+}
+
+impl BoardPiece {
+    pub fn new(color: PieceColor, piece_type: PieceType) -> Self {
+        Self { color, piece_type }
+    }
+}
+
+// Moves ======================================================================================== //
+/// Squares are `(x, y)` pairs like `Piece.x`/`Piece.y`: `x` is the rank, `y` the file.
+pub type Coord = (u8, u8);
+
+/// Algebraic name of a square, `(0, 4)` is "e1"
+pub fn square_name(square: Coord) -> String {
+    format!("{}{}", (b'a' + square.1) as char, (b'1' + square.0) as char)
+}
+
+/// Parses an algebraic square name like "e1"
+pub fn parse_square(name: &str) -> Option<Coord> {
+    match name.as_bytes() {
+        [file @ b'a'..=b'h', rank @ b'1'..=b'8'] => Some((rank - b'1', file - b'a')),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Move {
+    pub from: Coord,
+    pub to: Coord,
+    /// What a pawn turns into when it reaches the last rank
+    pub promotion: Option<PieceType>,
+}
+
+impl Move {
+    pub fn new(from: Coord, to: Coord) -> Self {
+        Self {
+            from,
+            to,
+            promotion: None,
+        }
+    }
+
+    pub fn with_promotion(from: Coord, to: Coord, piece_type: PieceType) -> Self {
+        Self {
+            from,
+            to,
+            promotion: Some(piece_type),
+        }
+    }
+}
+
+/// Moves print in UCI notation, which needs no position to make sense
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", crate::notation::to_uci(*self))
+    }
+}
+
+/// Pieces a pawn may promote to, most useful first
+pub const PROMOTION_TYPES: [PieceType; 4] = [
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+];
+
+/// Everything a move did to the board, so that a view can mirror it without knowing the rules
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MoveEffects {
+    /// Square of the piece that was taken, if any
+    pub captured: Option<Coord>,
+    /// Pieces that changed squares, as `(from, to)`. Castling relocates both the king and
+    /// the rook.
+    pub relocated: Vec<(Coord, Coord)>,
+    /// Square of a pawn that was promoted, and what it became
+    pub promoted: Option<(Coord, BoardPiece)>,
+    /// What `Position::unmake_move` puts back: the piece taken, and the castling rights, en
+    /// passant square and halfmove clock from before the move
+    captured_piece: Option<BoardPiece>,
+    castling: CastlingRights,
+    en_passant: Option<Coord>,
+    halfmove_clock: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveError {
+    /// There is no piece on the starting square
+    NoPiece,
+    /// The piece on the starting square belongs to the side not on move
+    NotYourTurn,
+    /// The piece can't move that way, or doing so would leave its king in check
+    Illegal,
+    /// The game has already ended
+    GameOver,
+}
+
+// Game results ================================================================================= //
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+impl GameResult {
+    pub fn win_for(color: PieceColor) -> Self {
+        match color {
+            PieceColor::White => GameResult::WhiteWins,
+            PieceColor::Black => GameResult::BlackWins,
+        }
+    }
+
+    pub fn winner(self) -> Option<PieceColor> {
+        match self {
+            GameResult::WhiteWins => Some(PieceColor::White),
+            GameResult::BlackWins => Some(PieceColor::Black),
+            GameResult::Draw => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameOverReason {
+    Checkmate,
+    Stalemate,
+    /// Neither side can possibly mate. Automatic.
+    InsufficientMaterial,
+    /// Claimed after 50 moves by each side without a capture or pawn move
+    FiftyMoveRule,
+    /// Automatic after 75 moves by each side without a capture or pawn move
+    SeventyFiveMoveRule,
+    /// Claimed when the same position occurs for the third time
+    ThreefoldRepetition,
+    /// Automatic when the same position occurs for the fifth time
+    FivefoldRepetition,
+    /// A player ran out of time
+    Timeout,
+    /// A player ran out of time, but the opponent couldn't mate by any series of legal moves
+    TimeoutVsInsufficientMaterial,
+}
+
+impl GameOverReason {
+    /// Draws that only end the game when a player claims them
+    pub fn is_claimable(self) -> bool {
+        matches!(
+            self,
+            GameOverReason::FiftyMoveRule | GameOverReason::ThreefoldRepetition
+        )
+    }
+}
+
+/// How and why a game ended. Also sent as an event by `BoardPlugin`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameOver {
+    pub result: GameResult,
+    pub reason: GameOverReason,
+}
+
+// Castling rights ============================================================================== //
+/// Which castles are still allowed, the `KQkq` of FEN. A right is lost for good once the king
+/// or that rook moves, or the rook is taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CastlingRights {
+    pub white_kingside: bool,
+    pub white_queenside: bool,
+    pub black_kingside: bool,
+    pub black_queenside: bool,
+}
+
+impl CastlingRights {
+    pub fn all() -> Self {
+        Self {
+            white_kingside: true,
+            white_queenside: true,
+            black_kingside: true,
+            black_queenside: true,
+        }
+    }
+
+    pub fn has(&self, color: PieceColor, kingside: bool) -> bool {
+        match (color, kingside) {
+            (PieceColor::White, true) => self.white_kingside,
+            (PieceColor::White, false) => self.white_queenside,
+            (PieceColor::Black, true) => self.black_kingside,
+            (PieceColor::Black, false) => self.black_queenside,
+        }
+    }
+
+    pub fn set(&mut self, color: PieceColor, kingside: bool, allowed: bool) {
+        match (color, kingside) {
+            (PieceColor::White, true) => self.white_kingside = allowed,
+            (PieceColor::White, false) => self.white_queenside = allowed,
+            (PieceColor::Black, true) => self.black_kingside = allowed,
+            (PieceColor::Black, false) => self.black_queenside = allowed,
+        }
+    }
+}
+
+/// Rank the pieces of `color` start on
+fn home_rank(color: PieceColor) -> u8 {
+    match color {
+        PieceColor::White => 0,
+        PieceColor::Black => 7,
+    }
+}
+
+// Position ===================================================================================== //
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Position {
+    /// Squares of each kind of piece, both colors together, by `PieceType::index`
+    piece_bitboards: [Bitboard; 6],
+    /// Squares of each side's pieces, by `PieceColor::index`
+    color_bitboards: [Bitboard; 2],
+    turn: PieceColor,
+    /// Square a pawn skipped over with a double step on the last move, where it can be taken
+    /// en passant
+    en_passant: Option<Coord>,
+    castling: CastlingRights,
+    /// Half moves since the last capture or pawn move
+    halfmove_clock: u32,
+    /// Starts at 1 and goes up after every black move
+    fullmove_number: u32,
+    /// Zobrist key of the pieces, castling rights and side to move, kept up to date as they
+    /// change. `key` adds the en passant part.
+    key: ZobristKey,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self::initial()
+    }
+}
+
+impl Position {
+    /// An empty board with white to move
+    pub fn empty() -> Self {
+        Self {
+            piece_bitboards: [0; 6],
+            color_bitboards: [0; 2],
+            turn: PieceColor::White,
+            en_passant: None,
+            castling: CastlingRights::default(),
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            key: turn_key(),
+        }
+    }
+
+    /// The standard starting position
+    pub fn initial() -> Self {
+        let back_rank = [
+            PieceType::Rook,
+            PieceType::Knight,
+            PieceType::Bishop,
+            PieceType::Queen,
+            PieceType::King,
+            PieceType::Bishop,
+            PieceType::Knight,
+            PieceType::Rook,
+        ];
+
+        let mut position = Self::empty();
+        for (y, piece_type) in back_rank.iter().enumerate() {
+            let y = y as u8;
+            position.set_piece(
+                (0, y),
+                Some(BoardPiece::new(PieceColor::White, *piece_type)),
+            );
+            position.set_piece(
+                (1, y),
+                Some(BoardPiece::new(PieceColor::White, PieceType::Pawn)),
+            );
+            position.set_piece(
+                (6, y),
+                Some(BoardPiece::new(PieceColor::Black, PieceType::Pawn)),
+            );
+            position.set_piece(
+                (7, y),
+                Some(BoardPiece::new(PieceColor::Black, *piece_type)),
+            );
+        }
+        position.set_castling(CastlingRights::all());
+        position
+    }
+
+    pub fn turn(&self) -> PieceColor {
+        self.turn
+    }
+
+    pub fn set_turn(&mut self, color: PieceColor) {
+        if color != self.turn {
+            self.key ^= turn_key();
+        }
+        self.turn = color;
+    }
+
+    pub fn en_passant(&self) -> Option<Coord> {
+        self.en_passant
+    }
+
+    pub fn set_en_passant(&mut self, square: Option<Coord>) {
+        self.en_passant = square;
+    }
+
+    pub fn castling(&self) -> CastlingRights {
+        self.castling
+    }
+
+    pub fn set_castling(&mut self, castling: CastlingRights) {
+        self.key ^= castling_key(self.castling) ^ castling_key(castling);
+        self.castling = castling;
+    }
+
+    pub fn halfmove_clock(&self) -> u32 {
+        self.halfmove_clock
+    }
+
+    pub fn set_halfmove_clock(&mut self, halfmove_clock: u32) {
+        self.halfmove_clock = halfmove_clock;
+    }
+
+    pub fn fullmove_number(&self) -> u32 {
+        self.fullmove_number
+    }
+
+    pub fn set_fullmove_number(&mut self, fullmove_number: u32) {
+        self.fullmove_number = fullmove_number;
+    }
+
+    pub fn piece_at(&self, square: Coord) -> Option<BoardPiece> {
+        let bit = square_bit(square);
+        let color = *PieceColor::ALL
+            .iter()
+            .find(|color| self.color_bitboards[color.index()] & bit != 0)?;
+        let piece_type = *PieceType::ALL
+            .iter()
+            .find(|piece_type| self.piece_bitboards[piece_type.index()] & bit != 0)?;
+        Some(BoardPiece::new(color, piece_type))
+    }
+
+    pub fn set_piece(&mut self, square: Coord, piece: Option<BoardPiece>) {
+        if let Some(old) = self.piece_at(square) {
+            self.key ^= piece_key(square, old);
+        }
+        if let Some(new) = piece {
+            self.key ^= piece_key(square, new);
+        }
+
+        let bit = square_bit(square);
+        for bitboard in self
+            .piece_bitboards
+            .iter_mut()
+            .chain(self.color_bitboards.iter_mut())
+        {
+            *bitboard &= !bit;
+        }
+        if let Some(piece) = piece {
+            self.piece_bitboards[piece.piece_type.index()] |= bit;
+            self.color_bitboards[piece.color.index()] |= bit;
+        }
+    }
+
+    /// Every occupied square with its piece, rank by rank
+    pub fn pieces(&self) -> impl Iterator<Item = (Coord, BoardPiece)> + '_ {
+        squares(self.occupied())
+            .filter_map(move |square| self.piece_at(square).map(|piece| (square, piece)))
+    }
+
+    /// Polyglot-compatible Zobrist key. Like in Polyglot, the en passant file only counts when
+    /// a pawn of the side to move stands next to the pawn that just made a double step.
+    pub fn key(&self) -> ZobristKey {
+        let en_passant = match self.en_passant {
+            // A pawn of the side to move that could take on the square stands where a pawn of
+            // the other side on that square would attack
+            Some(square)
+                if pawn_attacks(square, self.turn.opposite())
+                    & self.bitboard(self.turn, PieceType::Pawn)
+                    != 0 =>
+            {
+                en_passant_key(square.1)
+            }
+            _ => 0,
+        };
+        self.key ^ en_passant
+    }
+
+    /// Squares of the pieces of `color` and `piece_type`
+    pub fn bitboard(&self, color: PieceColor, piece_type: PieceType) -> Bitboard {
+        self.piece_bitboards[piece_type.index()] & self.color_bitboards[color.index()]
+    }
+
+    /// Squares of all pieces of `color`
+    pub fn color_bitboard(&self, color: PieceColor) -> Bitboard {
+        self.color_bitboards[color.index()]
+    }
+
+    pub fn occupied(&self) -> Bitboard {
+        self.color_bitboards[0] | self.color_bitboards[1]
+    }
+
+    /// Squares a `piece` on `square` attacks, whatever stands on them
+    fn attacks_from(&self, square: Coord, piece: BoardPiece) -> Bitboard {
+        let occupied = self.occupied();
+        match piece.piece_type {
+            PieceType::King => king_attacks(square),
+            PieceType::Queen => queen_attacks(square, occupied),
+            PieceType::Bishop => bishop_attacks(square, occupied),
+            PieceType::Knight => knight_attacks(square),
+            PieceType::Rook => rook_attacks(square, occupied),
+            PieceType::Pawn => pawn_attacks(square, piece.color),
+        }
+    }
+
+    /// Squares of the pieces of color `by` attacking `square`
+    pub fn attackers_to(&self, square: Coord, by: PieceColor) -> Bitboard {
+        let occupied = self.occupied();
+        let queens = self.bitboard(by, PieceType::Queen);
+        (knight_attacks(square) & self.bitboard(by, PieceType::Knight))
+            | (king_attacks(square) & self.bitboard(by, PieceType::King))
+            // A pawn of `by` attacks `square` if a pawn of the other color there would attack it
+            | (pawn_attacks(square, by.opposite()) & self.bitboard(by, PieceType::Pawn))
+            | (bishop_attacks(square, occupied) & (self.bitboard(by, PieceType::Bishop) | queens))
+            | (rook_attacks(square, occupied) & (self.bitboard(by, PieceType::Rook) | queens))
+    }
+
+    /// True if any piece of color `by` attacks `square`
+    pub fn is_square_attacked(&self, square: Coord, by: PieceColor) -> bool {
+        self.attackers_to(square, by) != 0
+    }
+
+    pub fn king_square(&self, color: PieceColor) -> Option<Coord> {
+        squares(self.bitboard(color, PieceType::King)).next()
+    }
+
+    /// True if the king of `color` is attacked
+    pub fn is_in_check(&self, color: PieceColor) -> bool {
+        match self.king_square(color) {
+            Some(square) => self.is_square_attacked(square, color.opposite()),
+            None => false,
+        }
+    }
+
+    /// Moves the piece on `from` can make by geometry alone, whoever's turn it is, including
+    /// the ones that leave its own king attacked
+    fn piece_moves(&self, from: Coord, moves: &mut Vec<Move>) {
+        let piece = match self.piece_at(from) {
+            Some(piece) => piece,
+            None => return,
+        };
+        let own = self.color_bitboard(piece.color);
+        let enemy = self.color_bitboard(piece.color.opposite());
+
+        let targets = if piece.piece_type == PieceType::Pawn {
+            let (forward, start_rank): (i8, u8) = match piece.color {
+                PieceColor::White => (1, 1),
+                PieceColor::Black => (-1, 6),
+            };
+            let en_passant = self.en_passant.map_or(0, square_bit);
+            let mut targets = pawn_attacks(from, piece.color) & (enemy | en_passant);
+
+            // Straight ahead onto empty squares, two of them from the starting rank
+            let one = ((from.0 as i8 + forward) as u8, from.1);
+            if one.0 < 8 && self.piece_at(one).is_none() {
+                targets |= square_bit(one);
+                let two = ((one.0 as i8 + forward) as u8, from.1);
+                if from.0 == start_rank && self.piece_at(two).is_none() {
+                    targets |= square_bit(two);
+                }
+            }
+            targets
+        } else {
+            let mut targets = self.attacks_from(from, piece) & !own;
+            if piece.piece_type == PieceType::King && from == (home_rank(piece.color), 4) {
+                for (kingside, file) in [(true, 6), (false, 2)].iter() {
+                    if self.can_castle(piece.color, *kingside) {
+                        targets |= square_bit((from.0, *file));
+                    }
+                }
+            }
+            targets
+        };
+
+        // Pawns reaching the last rank must promote
+        let last_rank = home_rank(piece.color.opposite());
+        for to in squares(targets) {
+            if piece.piece_type == PieceType::Pawn && to.0 == last_rank {
+                for piece_type in PROMOTION_TYPES.iter() {
+                    moves.push(Move::with_promotion(from, to, *piece_type));
+                }
+            } else {
+                moves.push(Move::new(from, to));
+            }
+        }
+    }
+
+    /// Checks the geometry of a move: the piece can reach the target square and nothing is
+    /// in the way. Turn order is left to `Game`.
+    pub fn is_move_valid(&self, mv: Move) -> bool {
+        let mut moves = Vec::new();
+        self.piece_moves(mv.from, &mut moves);
+        moves.contains(&mv)
+    }
+
+    /// Every move the side to move can make by piece geometry alone, including the ones that
+    /// leave its own king attacked
+    pub fn pseudo_legal_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        for from in squares(self.color_bitboard(self.turn)) {
+            self.piece_moves(from, &mut moves);
+        }
+        moves
+    }
+
+    /// Pseudo-legal moves that don't leave the mover's king attacked. This takes care of pins,
+    /// discovered checks and the king walking into an attack.
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut after = self.clone();
+        self.pseudo_legal_moves()
+            .into_iter()
+            .filter(|mv| {
+                let effects = after.apply_move(*mv);
+                let legal = !after.is_in_check(self.turn);
+                after.unmake_move(*mv, &effects);
+                legal
+            })
+            .collect()
+    }
+
+    /// Legal moves of the piece on `from`
+    pub fn legal_moves_from(&self, from: Coord) -> Vec<Move> {
+        self.legal_moves()
+            .into_iter()
+            .filter(|mv| mv.from == from)
+            .collect()
+    }
+
+    /// True if `mv` takes a piece, en passant included
+    pub fn is_capture(&self, mv: Move) -> bool {
+        self.piece_at(mv.to).is_some()
+            || (self.piece_at(mv.from).map(|piece| piece.piece_type) == Some(PieceType::Pawn)
+                && Some(mv.to) == self.en_passant)
+    }
+
+    /// True if neither side can ever mate: bare kings, a single minor piece, or bishops that
+    /// all stand on squares of the same color
+    pub fn is_insufficient_material(&self) -> bool {
+        let mut knights = 0;
+        let mut bishop_square_colors = Vec::new();
+        for (square, piece) in self.pieces() {
+            match piece.piece_type {
+                PieceType::King => {}
+                PieceType::Knight => knights += 1,
+                PieceType::Bishop => bishop_square_colors.push((square.0 + square.1) % 2),
+                _ => return false,
+            }
+        }
+
+        match (knights, bishop_square_colors.len()) {
+            (0, _) => bishop_square_colors
+                .windows(2)
+                .all(|pair| pair[0] == pair[1]),
+            (1, 0) => true,
+            _ => false,
+        }
+    }
+
+    /// False if `color` can't mate by any series of legal moves, however the other side plays.
+    /// Decides whether running out of time loses or draws.
+    ///
+    /// A lone knight, or bishops all on squares of one color, can only mate with the other
+    /// side's pieces hemming its king in, which same-colored bishops never do.
+    pub fn can_mate(&self, color: PieceColor) -> bool {
+        let square_color = |square: Coord| ((square.0 + square.1) % 2) as usize;
+        let mut knights = 0;
+        let mut bishop_square_colors = [false; 2];
+        for (square, piece) in self.pieces().filter(|(_, piece)| piece.color == color) {
+            match piece.piece_type {
+                PieceType::King => {}
+                PieceType::Knight => knights += 1,
+                PieceType::Bishop => bishop_square_colors[square_color(square)] = true,
+                _ => return true,
+            }
+        }
+
+        let bishop_colors = bishop_square_colors.iter().filter(|used| **used).count();
+        match (knights, bishop_colors) {
+            (0, 0) => false,
+            (1, 0) | (0, 1) => {
+                self.pieces()
+                    .filter(|(_, piece)| piece.color != color)
+                    .any(|(square, piece)| match piece.piece_type {
+                        PieceType::King => false,
+                        PieceType::Bishop => !bishop_square_colors[square_color(square)],
+                        _ => true,
+                    })
+            }
+            _ => true,
+        }
+    }
+
+    /// A king on its home square stepping two files along the back rank
+    pub fn is_castling(&self, mv: Move) -> bool {
+        match self.piece_at(mv.from) {
+            Some(BoardPiece {
+                piece_type: PieceType::King,
+                color,
+            }) => {
+                let rank = home_rank(color);
+                mv.from == (rank, 4) && mv.to.0 == rank && (mv.to.1 == 2 || mv.to.1 == 6)
+            }
+            _ => false,
+        }
+    }
+
+    /// FIDE preconditions for castling, apart from the king ending up attacked which
+    /// `legal_moves` already rules out: the right hasn't been lost, the rook is in its corner,
+    /// every square between them is empty and the king is neither in check nor crossing an
+    /// attacked square.
+    fn can_castle(&self, color: PieceColor, kingside: bool) -> bool {
+        let rank = home_rank(color);
+        // Files f and g, or b, c and d of the first rank
+        let (rook_file, between): (u8, Bitboard) = if kingside { (7, 0x60) } else { (0, 0x0e) };
+        if !self.castling.has(color, kingside)
+            || self.piece_at((rank, rook_file)) != Some(BoardPiece::new(color, PieceType::Rook))
+            || self.occupied() & (between << (8 * rank)) != 0
+        {
+            return false;
+        }
+
+        let king_files = if kingside { [4, 5, 6] } else { [4, 3, 2] };
+        king_files
+            .iter()
+            .all(|file| !self.is_square_attacked((rank, *file), color.opposite()))
+    }
+
+    /// Plays `mv` on the board without checking it and hands the turn to the other side
+    pub fn apply_move(&mut self, mv: Move) -> MoveEffects {
+        let mut effects = MoveEffects {
+            castling: self.castling,
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+            ..MoveEffects::default()
+        };
+        let piece = match self.piece_at(mv.from) {
+            Some(piece) => piece,
+            None => return effects,
+        };
+
+        if self.is_castling(mv) {
+            let (rook_from, rook_to) = if mv.to.1 == 6 {
+                ((mv.from.0, 7), (mv.from.0, 5))
+            } else {
+                ((mv.from.0, 0), (mv.from.0, 3))
+            };
+            let rook = self.piece_at(rook_from);
+            self.set_piece(rook_from, None);
+            self.set_piece(rook_to, rook);
+            effects.relocated.push((rook_from, rook_to));
+        }
+
+        if let Some(captured) = self.piece_at(mv.to) {
+            effects.captured = Some(mv.to);
+            effects.captured_piece = Some(captured);
+        } else if piece.piece_type == PieceType::Pawn && Some(mv.to) == self.en_passant {
+            // The bypassed pawn stands next to the mover, not on the target square
+            let bypassed = (mv.from.0, mv.to.1);
+            effects.captured_piece = self.piece_at(bypassed);
+            self.set_piece(bypassed, None);
+            effects.captured = Some(bypassed);
+        }
+        self.set_piece(mv.from, None);
+        self.set_piece(mv.to, Some(piece));
+        effects.relocated.push((mv.from, mv.to));
+
+        if piece.piece_type == PieceType::Pawn || effects.captured.is_some() {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+
+        if let Some(piece_type) = mv.promotion {
+            let promoted = BoardPiece::new(piece.color, piece_type);
+            self.set_piece(mv.to, Some(promoted));
+            effects.promoted = Some((mv.to, promoted));
+        }
+
+        // Moving the king loses both castles, and anything leaving or landing on a corner
+        // loses the castle with that rook
+        let mut castling = self.castling;
+        if piece.piece_type == PieceType::King {
+            castling.set(piece.color, true, false);
+            castling.set(piece.color, false, false);
+        }
+        for square in [mv.from, mv.to].iter() {
+            match *square {
+                (0, 0) => castling.white_queenside = false,
+                (0, 7) => castling.white_kingside = false,
+                (7, 0) => castling.black_queenside = false,
+                (7, 7) => castling.black_kingside = false,
+                _ => {}
+            }
+        }
+        self.set_castling(castling);
+
+        self.en_passant = if piece.piece_type == PieceType::Pawn
+            && (mv.to.0 as i8 - mv.from.0 as i8).abs() == 2
+        {
+            Some(((mv.from.0 + mv.to.0) / 2, mv.from.1))
+        } else {
+            None
+        };
+
+        if self.turn == PieceColor::Black {
+            self.fullmove_number += 1;
+        }
+        self.set_turn(self.turn.opposite());
+        effects
+    }
+
+    /// Takes back `mv`, the last move `apply_move` played, from what it returned. The key is
+    /// restored piece by piece like `apply_move` updates it, which is cheaper than keeping a
+    /// copy of the position around.
+    pub fn unmake_move(&mut self, mv: Move, effects: &MoveEffects) {
+        let piece = match (self.piece_at(mv.to), effects.promoted) {
+            (Some(piece), Some(_)) => BoardPiece::new(piece.color, PieceType::Pawn),
+            (Some(piece), None) => piece,
+            (None, _) => return,
+        };
+        self.set_piece(mv.to, None);
+        self.set_piece(mv.from, Some(piece));
+        if let Some(square) = effects.captured {
+            self.set_piece(square, effects.captured_piece);
+        }
+        // The rook of a castle
+        for (from, to) in effects
+            .relocated
+            .iter()
+            .filter(|(from, _)| *from != mv.from)
+        {
+            let rook = self.piece_at(*to);
+            self.set_piece(*to, None);
+            self.set_piece(*from, rook);
+        }
+
+        self.set_castling(effects.castling);
+        self.en_passant = effects.en_passant;
+        self.halfmove_clock = effects.halfmove_clock;
+        self.set_turn(self.turn.opposite());
+        if self.turn == PieceColor::Black {
+            self.fullmove_number -= 1;
+        }
+    }
+}
+
+/// Every legal move for the side to move in `position`
+pub fn legal_moves(position: &Position) -> Vec<Move> {
+    position.legal_moves()
+}
+
+// Game ========================================================================================= //
+/// A position together with the moves that led to it
+#[derive(Debug, Clone, Default)]
+pub struct Game {
+    position: Position,
+    moves: Vec<Move>,
+    /// Position before each move in `moves`
+    history: Vec<Position>,
+    /// Set once a player claims a draw or runs out of time
+    ended: Option<GameOver>,
+    /// Moves taken back with `undo`, the most recent last
+    undone: Vec<Move>,
+}
+
+impl Game {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_position(position: Position) -> Self {
+        Self {
+            position,
+            ..Self::default()
+        }
+    }
+
+    pub fn position(&self) -> &Position {
+        &self.position
+    }
+
+    pub fn turn(&self) -> PieceColor {
+        self.position.turn()
+    }
+
+    /// Moves played so far, oldest first
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    /// The position the game started from
+    pub fn starting_position(&self) -> &Position {
+        self.history.first().unwrap_or(&self.position)
+    }
+
+    /// The position after the first `ply` half moves, the current one if `ply` is past the end
+    pub fn position_at(&self, ply: usize) -> &Position {
+        self.history.get(ply).unwrap_or(&self.position)
+    }
+
+    /// How many times the current position has occurred, counting this one. Only positions
+    /// since the last capture or pawn move with the same side to move can be the same, and the
+    /// key tells them apart.
+    pub fn repetitions(&self) -> usize {
+        let key = self.position.key();
+        1 + self
+            .history
+            .iter()
+            .rev()
+            .take(self.position.halfmove_clock() as usize)
+            .skip(1)
+            .step_by(2)
+            .filter(|position| position.key() == key)
+            .count()
+    }
+
+    /// `Some` once the game is over: mate, stalemate, an automatic draw or a claimed one
+    pub fn outcome(&self) -> Option<GameOver> {
+        if self.ended.is_some() {
+            return self.ended;
+        }
+
+        let turn = self.position.turn();
+        let draw = |reason| {
+            Some(GameOver {
+                result: GameResult::Draw,
+                reason,
+            })
+        };
+        if legal_moves(&self.position).is_empty() {
+            return if self.position.is_in_check(turn) {
+                Some(GameOver {
+                    result: GameResult::win_for(turn.opposite()),
+                    reason: GameOverReason::Checkmate,
+                })
+            } else {
+                draw(GameOverReason::Stalemate)
+            };
+        }
+
+        if self.position.is_insufficient_material() {
+            draw(GameOverReason::InsufficientMaterial)
+        } else if self.position.halfmove_clock() >= 150 {
+            draw(GameOverReason::SeventyFiveMoveRule)
+        } else if self.repetitions() >= 5 {
+            draw(GameOverReason::FivefoldRepetition)
+        } else {
+            None
+        }
+    }
+
+    /// A draw the side to move could claim right now, if any
+    pub fn claimable_draw(&self) -> Option<GameOverReason> {
+        if self.outcome().is_some() {
+            None
+        } else if self.repetitions() >= 3 {
+            Some(GameOverReason::ThreefoldRepetition)
+        } else if self.position.halfmove_clock() >= 100 {
+            Some(GameOverReason::FiftyMoveRule)
+        } else {
+            None
+        }
+    }
+
+    /// Ends the game in a draw if one can be claimed
+    pub fn claim_draw(&mut self) -> Option<GameOver> {
+        let reason = self.claimable_draw()?;
+        self.ended = Some(GameOver {
+            result: GameResult::Draw,
+            reason,
+        });
+        self.ended
+    }
+
+    /// Ends the game because `color` ran out of time: a loss, unless the opponent can't mate
+    pub fn time_out(&mut self, color: PieceColor) -> Option<GameOver> {
+        if self.outcome().is_some() {
+            return None;
+        }
+        self.ended = Some(if self.position.can_mate(color.opposite()) {
+            GameOver {
+                result: GameResult::win_for(color.opposite()),
+                reason: GameOverReason::Timeout,
+            }
+        } else {
+            GameOver {
+                result: GameResult::Draw,
+                reason: GameOverReason::TimeoutVsInsufficientMaterial,
+            }
+        });
+        self.ended
+    }
+
+    /// True if moving the piece on `from` to `to` is legal but needs a promotion choice first
+    pub fn is_promotion(&self, from: Coord, to: Coord) -> bool {
+        legal_moves(&self.position).contains(&Move::with_promotion(from, to, PieceType::Queen))
+    }
+
+    /// Validates `mv` for the side to move and plays it
+    pub fn make_move(&mut self, mv: Move) -> Result<MoveEffects, MoveError> {
+        let piece = self.position.piece_at(mv.from).ok_or(MoveError::NoPiece)?;
+        if piece.color != self.position.turn() {
+            return Err(MoveError::NotYourTurn);
+        }
+        if self.outcome().is_some() {
+            return Err(MoveError::GameOver);
+        }
+        if !legal_moves(&self.position).contains(&mv) {
+            return Err(MoveError::Illegal);
+        }
+
+        self.history.push(self.position.clone());
+        let effects = self.position.apply_move(mv);
+        self.moves.push(mv);
+        self.undone.clear();
+        Ok(effects)
+    }
+
+    /// Takes back the last move, and a claimed draw or timeout with it. Returns the move, which
+    /// `redo` can play again.
+    pub fn undo(&mut self) -> Option<Move> {
+        let mv = self.moves.pop()?;
+        self.position = self.history.pop()?;
+        self.ended = None;
+        self.undone.push(mv);
+        Some(mv)
+    }
+
+    /// Plays the last undone move again. Playing any other move forgets the undone ones.
+    pub fn redo(&mut self) -> Option<Move> {
+        let mv = self.undone.pop()?;
+        let undone = std::mem::take(&mut self.undone);
+        let played = self.make_move(mv);
+        self.undone = undone;
+        played.ok().map(|_| mv)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.moves.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.undone.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::parse_fen;
+    use crate::notation::parse_san;
+
+    fn position(fen: &str) -> Position {
+        parse_fen(fen).unwrap()
+    }
+
+    fn square(name: &str) -> Coord {
+        parse_square(name).unwrap()
+    }
+
+    fn mv(from: &str, to: &str) -> Move {
+        Move::new(square(from), square(to))
+    }
+
+    /// Plays moves given in SAN, separated by spaces
+    fn play(game: &mut Game, moves: &str) {
+        for san in moves.split_whitespace() {
+            let mv = parse_san(game.position(), san).unwrap();
+            game.make_move(mv).unwrap();
+        }
+    }
+
+    fn draw(reason: GameOverReason) -> Option<GameOver> {
+        Some(GameOver {
+            result: GameResult::Draw,
+            reason,
+        })
+    }
+
+    #[test]
+    fn legal_moves_keep_the_king_safe() {
+        assert_eq!(Position::initial().legal_moves().len(), 20);
+
+        // The knight is pinned to its king
+        let pinned = position("4k3/4r3/8/8/8/8/4N3/4K3 w - - 0 1");
+        assert!(pinned.legal_moves_from(square("e2")).is_empty());
+        assert!(pinned
+            .pseudo_legal_moves()
+            .iter()
+            .any(|mv| mv.from == square("e2")));
+        assert_eq!(pinned.legal_moves().len(), 4);
+
+        // In check, the king may not stay on the rook's file
+        let check = position("4k3/4r3/8/8/8/8/8/R3K3 w - - 0 1");
+        assert!(check.is_in_check(PieceColor::White));
+        let mut moves = check.legal_moves();
+        moves.sort_by_key(|mv| mv.to);
+        assert_eq!(
+            moves,
+            vec![
+                mv("e1", "d1"),
+                mv("e1", "f1"),
+                mv("e1", "d2"),
+                mv("e1", "f2")
+            ]
+        );
+
+        let mut game = Game::new();
+        assert_eq!(game.make_move(mv("e7", "e5")), Err(MoveError::NotYourTurn));
+        assert_eq!(game.make_move(mv("e2", "e5")), Err(MoveError::Illegal));
+        assert_eq!(game.make_move(mv("e3", "e4")), Err(MoveError::NoPiece));
+    }
+
+    #[test]
+    fn unmake_move_restores_the_position() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "8/8/8/K2pP2r/8/8/8/7k w - d6 0 1",
+        ]
+        .iter()
+        {
+            let mut position = position(fen);
+            let original = position.clone();
+            for mv in original.legal_moves() {
+                let effects = position.apply_move(mv);
+                let after = position.clone();
+                for reply in after.legal_moves() {
+                    let reply_effects = position.apply_move(reply);
+                    position.unmake_move(reply, &reply_effects);
+                    assert_eq!(position, after, "{} {} in {}", mv, reply, fen);
+                }
+                position.unmake_move(mv, &effects);
+                // Keys included
+                assert_eq!(position, original, "{} in {}", mv, fen);
+            }
+        }
+    }
+
+    #[test]
+    fn checkmate_and_stalemate() {
+        let mut game = Game::new();
+        play(&mut game, "f3 e5 g4 Qh4");
+        assert_eq!(
+            game.outcome(),
+            Some(GameOver {
+                result: GameResult::BlackWins,
+                reason: GameOverReason::Checkmate,
+            })
+        );
+        assert_eq!(game.make_move(mv("a2", "a3")), Err(MoveError::GameOver));
+
+        let stalemate = Game::from_position(position("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"));
+        assert_eq!(stalemate.outcome(), draw(GameOverReason::Stalemate));
+    }
+
+    #[test]
+    fn en_passant() {
+        let mut game = Game::new();
+        play(&mut game, "e4 a6 e5 d5");
+        assert_eq!(game.position().en_passant(), Some(square("d6")));
+        let effects = game.make_move(mv("e5", "d6")).unwrap();
+        assert_eq!(effects.captured, Some(square("d5")));
+        assert_eq!(game.position().piece_at(square("d5")), None);
+
+        // Only right after the double step
+        let mut game = Game::new();
+        play(&mut game, "e4 d5 e5 a6");
+        assert!(!game.position().legal_moves().contains(&mv("e5", "d6")));
+
+        // Not when taking would open the rank to the king
+        let pinned = position("8/8/8/K2pP2r/8/8/8/7k w - d6 0 1");
+        assert!(!pinned.legal_moves().contains(&mv("e5", "d6")));
+    }
+
+    #[test]
+    fn castling() {
+        let start = position("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        let moves = start.legal_moves();
+        assert!(moves.contains(&mv("e1", "g1")) && moves.contains(&mv("e1", "c1")));
+
+        let mut castled = start.clone();
+        let effects = castled.apply_move(mv("e1", "g1"));
+        assert!(effects.relocated.contains(&(square("h1"), square("f1"))));
+        assert_eq!(
+            castled.piece_at(square("f1")),
+            Some(BoardPiece::new(PieceColor::White, PieceType::Rook))
+        );
+        assert!(!castled.castling().has(PieceColor::White, true));
+        assert!(!castled.castling().has(PieceColor::White, false));
+        assert!(castled.castling().has(PieceColor::Black, true));
+
+        // A rook leaving or taken in its corner loses only that castle
+        let mut rook_moved = start.clone();
+        rook_moved.apply_move(mv("h1", "h2"));
+        assert!(!rook_moved.castling().has(PieceColor::White, true));
+        assert!(rook_moved.castling().has(PieceColor::White, false));
+        let mut rook_taken = start.clone();
+        rook_taken.apply_move(mv("a1", "a8"));
+        assert!(!rook_taken.castling().has(PieceColor::White, false));
+        assert!(!rook_taken.castling().has(PieceColor::Black, false));
+        assert!(rook_taken.castling().has(PieceColor::Black, true));
+
+        let castles = |fen: &str| {
+            let position = position(fen);
+            let moves = position.legal_moves();
+            (
+                moves.contains(&mv("e1", "g1")),
+                moves.contains(&mv("e1", "c1")),
+            )
+        };
+        // Not out of check, through an attacked square or into check
+        assert_eq!(
+            castles("4k3/4r3/8/8/8/8/8/R3K2R w KQ - 0 1"),
+            (false, false)
+        );
+        assert_eq!(castles("4kr2/8/8/8/8/8/8/R3K2R w KQ - 0 1"), (false, true));
+        assert_eq!(castles("4k1r1/8/8/8/8/8/8/R3K2R w KQ - 0 1"), (false, true));
+        // The rook may cross an attacked square, but not a piece
+        assert_eq!(castles("1r2k3/8/8/8/8/8/8/R3K2R w KQ - 0 1"), (true, true));
+        assert_eq!(
+            castles("4k3/8/8/8/8/8/8/RN2K1NR w KQ - 0 1"),
+            (false, false)
+        );
+        // Nor without the right
+        assert_eq!(castles("4k3/8/8/8/8/8/8/R3K2R w Q - 0 1"), (false, true));
+    }
+
+    #[test]
+    fn promotion() {
+        let mut game = Game::from_position(position("3r4/4P3/8/8/8/8/k7/4K3 w - - 0 1"));
+        let moves = game.position().legal_moves_from(square("e7"));
+        assert_eq!(moves.len(), 8);
+        assert!(moves.iter().all(|mv| mv.promotion.is_some()));
+        assert!(game.is_promotion(square("e7"), square("d8")));
+        assert!(!game.is_promotion(square("e1"), square("e2")));
+
+        let knight = BoardPiece::new(PieceColor::White, PieceType::Knight);
+        let effects = game
+            .make_move(Move::with_promotion(
+                square("e7"),
+                square("e8"),
+                PieceType::Knight,
+            ))
+            .unwrap();
+        assert_eq!(effects.promoted, Some((square("e8"), knight)));
+        assert_eq!(game.position().piece_at(square("e8")), Some(knight));
+    }
+
+    #[test]
+    fn repetition_draws() {
+        let mut game = Game::new();
+        play(&mut game, "Nf3 Nf6 Ng1 Ng8 Nf3 Nf6 Ng1 Ng8");
+        assert_eq!(game.repetitions(), 3);
+        assert_eq!(game.outcome(), None);
+        assert_eq!(
+            game.claimable_draw(),
+            Some(GameOverReason::ThreefoldRepetition)
+        );
+
+        play(&mut game, "Nf3 Nf6 Ng1 Ng8 Nf3 Nf6 Ng1 Ng8");
+        assert_eq!(game.repetitions(), 5);
+        assert_eq!(game.outcome(), draw(GameOverReason::FivefoldRepetition));
+        assert_eq!(game.claimable_draw(), None);
+
+        let mut claimed = Game::new();
+        play(&mut claimed, "Nf3 Nf6 Ng1 Ng8 Nf3 Nf6 Ng1 Ng8");
+        assert_eq!(
+            claimed.claim_draw(),
+            draw(GameOverReason::ThreefoldRepetition)
+        );
+        assert_eq!(claimed.outcome(), draw(GameOverReason::ThreefoldRepetition));
+    }
+
+    #[test]
+    fn move_rule_draws() {
+        let mut fifty = Game::from_position(position("4k3/8/8/8/8/8/4P3/R3K3 w - - 99 80"));
+        assert_eq!(fifty.claimable_draw(), None);
+        play(&mut fifty, "Ra2");
+        assert_eq!(fifty.outcome(), None);
+        assert_eq!(fifty.claimable_draw(), Some(GameOverReason::FiftyMoveRule));
+        // A pawn move starts the count again
+        play(&mut fifty, "Kd7 e4");
+        assert_eq!(fifty.claimable_draw(), None);
+
+        let mut seventy_five = Game::from_position(position("4k3/8/8/8/8/8/4P3/R3K3 w - - 149 80"));
+        assert_eq!(seventy_five.outcome(), None);
+        play(&mut seventy_five, "Ra2");
+        assert_eq!(
+            seventy_five.outcome(),
+            draw(GameOverReason::SeventyFiveMoveRule)
+        );
+    }
+
+    #[test]
+    fn insufficient_material() {
+        let insufficient = |fen: &str| position(fen).is_insufficient_material();
+        assert!(insufficient("4k3/8/8/8/8/8/8/4K3 w - - 0 1"));
+        assert!(insufficient("4k3/8/8/8/8/8/8/4KN2 w - - 0 1"));
+        assert!(insufficient("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+        // Bishops on squares of the same color, whoever they belong to
+        assert!(insufficient("4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+        assert!(!insufficient("2b1k3/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+        assert!(!insufficient("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1"));
+        assert!(!insufficient("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"));
+        assert!(!insufficient("4k3/8/8/8/8/8/8/R3K3 w - - 0 1"));
+
+        let game = Game::from_position(position("4k3/8/8/8/8/8/8/4KN2 w - - 0 1"));
+        assert_eq!(game.outcome(), draw(GameOverReason::InsufficientMaterial));
+    }
+
+    #[test]
+    fn flag_fall_against_what_the_opponent_could_mate_with() {
+        let can_mate = |fen: &str| position(fen).can_mate(PieceColor::White);
+        assert!(!can_mate("4k3/8/8/8/8/8/8/4K3 b - - 0 1"));
+        assert!(!can_mate("4k3/8/8/8/8/8/8/4KN2 b - - 0 1"));
+        assert!(!can_mate("4k3/8/8/8/8/8/8/2B1K3 b - - 0 1"));
+        assert!(can_mate("4k3/8/8/8/8/8/8/1N2KN2 b - - 0 1"));
+        assert!(can_mate("4k3/8/8/8/8/8/8/2B1KN2 b - - 0 1"));
+        assert!(can_mate("4k3/8/8/8/8/8/8/2B1KB2 b - - 0 1"));
+        assert!(can_mate("4k3/8/8/8/8/8/4P3/4K3 b - - 0 1"));
+
+        // The flagged side's own pieces can stand in its king's way
+        assert!(can_mate("4k3/4p3/8/8/8/8/8/4KN2 b - - 0 1"));
+        assert!(can_mate("4kn2/8/8/8/8/8/8/4KN2 b - - 0 1"));
+        assert!(can_mate("4kb2/8/8/8/8/8/8/4KN2 b - - 0 1"));
+        assert!(can_mate("2b1k3/8/8/8/8/8/8/2B1K3 b - - 0 1"));
+        assert!(can_mate("4k3/8/8/8/8/8/8/r1B1K3 b - - 0 1"));
+        // But bishops on the same color as the opponent's never do
+        assert!(!can_mate("4kb2/8/8/8/8/8/8/2B1K3 b - - 0 1"));
+
+        let time_out = |fen: &str| {
+            let mut game = Game::from_position(position(fen));
+            game.time_out(PieceColor::Black)
+        };
+        assert_eq!(
+            time_out("4k3/4p3/8/8/8/8/8/4KN2 b - - 0 1"),
+            Some(GameOver {
+                result: GameResult::WhiteWins,
+                reason: GameOverReason::Timeout,
+            })
+        );
+        assert_eq!(
+            time_out("q3k3/8/8/8/8/8/8/4K3 b - - 0 1"),
+            draw(GameOverReason::TimeoutVsInsufficientMaterial)
+        );
+        assert_eq!(
+            time_out("4kb2/8/8/8/8/8/8/2B1K2N b - - 0 1"),
+            Some(GameOver {
+                result: GameResult::WhiteWins,
+                reason: GameOverReason::Timeout,
+            })
+        );
+    }
+}
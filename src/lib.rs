@@ -0,0 +1,14 @@
+//! The rules, notation and engine, with nothing drawn on screen: used by the 3D board and by
+//! the `rustic_chess_uci` engine.
+
+pub mod bitboard;
+pub mod engine;
+pub mod fen;
+pub mod game;
+pub mod notation;
+pub mod perft;
+pub mod pgn;
+pub mod transposition;
+pub mod uci;
+pub mod uci_client;
+pub mod zobrist;
@@ -1,17 +1,53 @@
 use bevy::prelude::*;
 use bevy_mod_picking::*;
+use std::io;
+use std::time::{Duration, Instant};
 
+mod clock;
+mod external;
+mod opponent;
 mod pieces;
 mod board;
+mod replay;
 mod ui;
 
+// The headless modules live in the library, the UCI engine uses them too
+use rustic_chess::{engine, fen, game, notation, perft, pgn, transposition, uci_client};
+
 use pieces::*;
 use crate::board::BoardPlugin;
+use crate::clock::{parse_time_control, Clock, ClockPlugin, GameClock};
+use crate::engine::SearchLimits;
+use crate::external::{ExternalEngine, ExternalEnginePlugin};
+use crate::fen::parse_fen;
+use crate::game::{Game, PieceColor};
+use crate::opponent::{EngineOpponent, OpponentPlugin};
+use crate::perft::divide;
+use crate::pgn::parse_pgn;
+use crate::replay::{Replay, ReplayPlugin};
+use crate::uci_client::{EngineConfig, UciClient};
 use crate::ui::UIPlugin;
 
+/// Where the external engine is set up, if anywhere
+const ENGINE_CONFIG_FILE: &str = "engine.cfg";
+
 fn main() {
+    if std::env::args().nth(1).as_deref() == Some("perft") {
+        run_perft();
+        return;
+    }
+
+    // A replay shows its games on the board instead of a new one
+    let replay = replay_from_args();
+    let game = replay.game().unwrap_or_else(starting_game);
+
     App::build()
         .add_resource(Msaa{samples: 4}) // anti-aliasing enabled
+        .add_resource(game)
+        .add_resource(replay)
+        .add_resource(clock_from_args())
+        .add_resource(opponent_from_args())
+        .add_resource(external_from_args())
         .add_resource(WindowDescriptor{               // setup window
             title: "LVI Chess".to_string(),
             width: 1600.,
@@ -23,10 +59,221 @@ fn main() {
         .add_plugin(BoardPlugin)
         .add_plugin(PiecesPlugin)
         .add_plugin(UIPlugin)
+        .add_plugin(ReplayPlugin)
+        .add_plugin(ClockPlugin)
+        .add_plugin(OpponentPlugin)
+        .add_plugin(ExternalEnginePlugin)
         .add_startup_system(setup.system())
         .run();
 }
 
+/// `perft <depth> [fen]`: counts the move tree below the position, split by first move, and
+/// exits without opening a window
+fn run_perft() {
+    let args: Vec<String> = std::env::args().skip(2).collect();
+    let depth = match args.first().and_then(|depth| depth.parse().ok()) {
+        Some(depth) => depth,
+        None => {
+            eprintln!("Usage: perft <depth> [fen]");
+            std::process::exit(1);
+        }
+    };
+    let position = if args.len() > 1 {
+        parse_fen(&args[1..].join(" ")).unwrap_or_else(|error| {
+            eprintln!("Invalid FEN: {}", error);
+            std::process::exit(1);
+        })
+    } else {
+        Game::new().position().clone()
+    };
+
+    let start = Instant::now();
+    let mut nodes = 0;
+    for (mv, count) in divide(&position, depth) {
+        println!("{}: {}", mv, count);
+        nodes += count;
+    }
+    let elapsed = start.elapsed();
+    println!();
+    println!("Nodes searched: {}", nodes);
+    println!(
+        "Time: {:.3}s ({:.0} nodes/s)",
+        elapsed.as_secs_f64(),
+        nodes as f64 / elapsed.as_secs_f64().max(1e-9)
+    );
+}
+
+/// Value following `name` on the command line, if `name` was given
+fn arg_value(name: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    let index = args.iter().position(|arg| arg == name)?;
+    match args.get(index + 1) {
+        Some(value) => Some(value.clone()),
+        None => {
+            eprintln!("{} needs a value", name);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Every value following `name` on the command line, for options that may be repeated
+fn arg_values(name: &str) -> Vec<String> {
+    let args: Vec<String> = std::env::args().collect();
+    args.windows(2)
+        .filter(|pair| pair[0] == name)
+        .map(|pair| pair[1].clone())
+        .collect()
+}
+
+/// Starts from the position given with `--fen "<fen>"`, or the usual one
+fn starting_game() -> Game {
+    let fen = match arg_value("--fen") {
+        Some(fen) => fen,
+        None => return Game::new(),
+    };
+
+    match parse_fen(&fen) {
+        Ok(position) => Game::from_position(position),
+        Err(error) => {
+            eprintln!("Invalid FEN: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Loads the games of the file given with `--pgn <file>` for replaying
+fn replay_from_args() -> Replay {
+    let path = match arg_value("--pgn") {
+        Some(path) => path,
+        None => return Replay::default(),
+    };
+
+    let text = std::fs::read_to_string(&path).unwrap_or_else(|error| {
+        eprintln!("Could not read {}: {}", path, error);
+        std::process::exit(1);
+    });
+    match parse_pgn(&text) {
+        Ok(games) if !games.is_empty() => Replay::new(games),
+        Ok(_) => {
+            eprintln!("{} has no games in it", path);
+            std::process::exit(1);
+        }
+        Err(error) => {
+            eprintln!("Invalid PGN in {}: {}", path, error);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Times the game with `--clock <time control>`, see `parse_time_control`
+fn clock_from_args() -> GameClock {
+    let spec = match arg_value("--clock") {
+        Some(spec) => spec,
+        None => return GameClock::default(),
+    };
+
+    match parse_time_control(&spec) {
+        Ok(control) => GameClock(Some(Clock::new(control))),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Lets the engine play a side with `--engine white|black`, thinking up to `--depth <plies>`
+/// or `--movetime <milliseconds>`, with a transposition table of `--hash <megabytes>`
+fn opponent_from_args() -> EngineOpponent {
+    let color = match arg_value("--engine").as_deref() {
+        Some("white") => Some(PieceColor::White),
+        Some("black") => Some(PieceColor::Black),
+        Some(other) => {
+            eprintln!("--engine takes white or black, not {}", other);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    let number = |name| {
+        arg_value(name).map(|value| {
+            value.parse::<u64>().unwrap_or_else(|_| {
+                eprintln!("{} takes a number, not {}", name, value);
+                std::process::exit(1);
+            })
+        })
+    };
+    let mut limits = EngineOpponent::default().limits;
+    if let Some(depth) = number("--depth") {
+        limits = SearchLimits {
+            depth: Some(depth as u32),
+            ..SearchLimits::default()
+        };
+    }
+    if let Some(movetime) = number("--movetime") {
+        limits.movetime = Some(Duration::from_millis(movetime));
+    }
+
+    let mut opponent = EngineOpponent {
+        color,
+        limits,
+        ..EngineOpponent::default()
+    };
+    if let Some(megabytes) = number("--hash") {
+        opponent.set_hash_size(megabytes as usize);
+    }
+    opponent
+}
+
+/// Runs the UCI engine set up in `engine.cfg`, or in the file given with `--uci-config <file>`,
+/// see `EngineConfig::parse`. `--uci-engine <path>` runs another engine, and each
+/// `--uci-option <name>=<value>` is set after the options of the file. The engine plays the
+/// side given with `--engine`, or analyses the position on the board.
+fn external_from_args() -> ExternalEngine {
+    let config_file = arg_value("--uci-config");
+    let config_path = config_file.as_deref().unwrap_or(ENGINE_CONFIG_FILE);
+    let mut config = match std::fs::read_to_string(config_path) {
+        Ok(text) => EngineConfig::parse(&text).unwrap_or_else(|error| {
+            eprintln!("Invalid engine config in {}: {}", config_path, error);
+            std::process::exit(1);
+        }),
+        // No engine.cfg only means the engine, if any, comes from the command line
+        Err(error) if config_file.is_none() && error.kind() == io::ErrorKind::NotFound => {
+            EngineConfig::default()
+        }
+        Err(error) => {
+            eprintln!("Could not read {}: {}", config_path, error);
+            std::process::exit(1);
+        }
+    };
+    if let Some(path) = arg_value("--uci-engine") {
+        config.path = path.into();
+    }
+    if config.path.as_os_str().is_empty() {
+        return ExternalEngine::default();
+    }
+
+    for option in arg_values("--uci-option") {
+        match option.find('=') {
+            Some(index) => config.options.push((
+                option[..index].trim().to_string(),
+                option[index + 1..].trim().to_string(),
+            )),
+            None => {
+                eprintln!("--uci-option takes <name>=<value>, not {}", option);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    match UciClient::start(&config) {
+        Ok(client) => ExternalEngine::new(client),
+        Err(error) => {
+            eprintln!("Could not start {}: {}", config.path.display(), error);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn setup(
     commands: &mut Commands,
     _meshes: ResMut<Assets<Mesh>>,
@@ -48,5 +295,3 @@ fn setup(
             ..Default::default()
         });
 }
-
-
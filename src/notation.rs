@@ -0,0 +1,367 @@
+//! Writing moves down and reading them back: Standard Algebraic Notation as used in PGN,
+//! long algebraic notation, and the coordinate notation spoken by UCI engines.
+
+use crate::game::{legal_moves, parse_square, square_name, Move, PieceColor, PieceType, Position};
+use std::fmt;
+
+/// Uppercase SAN letter of a piece, nothing for pawns
+pub fn piece_letter(piece_type: PieceType) -> &'static str {
+    match piece_type {
+        PieceType::King => "K",
+        PieceType::Queen => "Q",
+        PieceType::Bishop => "B",
+        PieceType::Knight => "N",
+        PieceType::Rook => "R",
+        PieceType::Pawn => "",
+    }
+}
+
+/// "12." before a white move, "12..." before a black one
+pub fn move_number(position: &Position) -> String {
+    match position.turn() {
+        PieceColor::White => format!("{}.", position.fullmove_number()),
+        PieceColor::Black => format!("{}...", position.fullmove_number()),
+    }
+}
+
+/// Check or mate suffix of `mv` played in `position`
+fn check_suffix(position: &Position, mv: Move) -> &'static str {
+    let mut after = position.clone();
+    after.apply_move(mv);
+    if !after.is_in_check(after.turn()) {
+        ""
+    } else if legal_moves(&after).is_empty() {
+        "#"
+    } else {
+        "+"
+    }
+}
+
+/// SAN of `mv` played in `position`, e.g. "Nbd7", "exd6", "e8=Q+", "O-O-O#". `mv` should be
+/// legal in `position`.
+pub fn to_san(position: &Position, mv: Move) -> String {
+    let piece = match position.piece_at(mv.from) {
+        Some(piece) => piece,
+        None => return String::from("--"),
+    };
+
+    let mut san = if position.is_castling(mv) {
+        String::from(if mv.to.1 == 6 { "O-O" } else { "O-O-O" })
+    } else {
+        let is_capture = position.piece_at(mv.to).is_some()
+            || (piece.piece_type == PieceType::Pawn && Some(mv.to) == position.en_passant());
+        let mut san = String::from(piece_letter(piece.piece_type));
+
+        if piece.piece_type == PieceType::Pawn {
+            if is_capture {
+                san.push((b'a' + mv.from.1) as char);
+            }
+        } else {
+            // Name the starting file, rank or both if another piece of the same kind could
+            // also go there
+            let others: Vec<Move> = legal_moves(position)
+                .into_iter()
+                .filter(|other| {
+                    other.to == mv.to
+                        && other.from != mv.from
+                        && position.piece_at(other.from) == Some(piece)
+                })
+                .collect();
+            if !others.is_empty() {
+                let from = square_name(mv.from);
+                if others.iter().all(|other| other.from.1 != mv.from.1) {
+                    san.push_str(&from[..1]);
+                } else if others.iter().all(|other| other.from.0 != mv.from.0) {
+                    san.push_str(&from[1..]);
+                } else {
+                    san.push_str(&from);
+                }
+            }
+        }
+
+        if is_capture {
+            san.push('x');
+        }
+        san.push_str(&square_name(mv.to));
+        if let Some(piece_type) = mv.promotion {
+            san.push('=');
+            san.push_str(piece_letter(piece_type));
+        }
+        san
+    };
+
+    san.push_str(check_suffix(position, mv));
+    san
+}
+
+/// Long algebraic notation of `mv` played in `position`, naming both squares: "Ng1-f3",
+/// "e4xd5", "e7-e8=Q+". Castling is written as in SAN.
+pub fn to_lan(position: &Position, mv: Move) -> String {
+    let piece = match position.piece_at(mv.from) {
+        Some(piece) => piece,
+        None => return String::from("--"),
+    };
+    if position.is_castling(mv) {
+        return to_san(position, mv);
+    }
+
+    let is_capture = position.piece_at(mv.to).is_some()
+        || (piece.piece_type == PieceType::Pawn && Some(mv.to) == position.en_passant());
+    let mut lan = format!(
+        "{}{}{}{}",
+        piece_letter(piece.piece_type),
+        square_name(mv.from),
+        if is_capture { 'x' } else { '-' },
+        square_name(mv.to)
+    );
+    if let Some(piece_type) = mv.promotion {
+        lan.push('=');
+        lan.push_str(piece_letter(piece_type));
+    }
+    lan.push_str(check_suffix(position, mv));
+    lan
+}
+
+/// UCI coordinate notation: "e2e4", "e1g1" for castling, "e7e8q" for promotions. Needs no
+/// position.
+pub fn to_uci(mv: Move) -> String {
+    let mut uci = format!("{}{}", square_name(mv.from), square_name(mv.to));
+    if let Some(piece_type) = mv.promotion {
+        uci.push_str(&piece_letter(piece_type).to_ascii_lowercase());
+    }
+    uci
+}
+
+/// Finds the legal move in `position` written `uci` in UCI coordinate notation
+pub fn parse_uci(position: &Position, uci: &str) -> Option<Move> {
+    legal_moves(position)
+        .into_iter()
+        .find(|mv| to_uci(*mv) == uci)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SanError {
+    /// Not SAN at all
+    Syntax(String),
+    /// Well formed, but no legal move matches
+    Illegal(String),
+    /// More than one legal move matches
+    Ambiguous(String),
+}
+
+impl fmt::Display for SanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SanError::Syntax(san) => write!(f, "\"{}\" is not a move", san),
+            SanError::Illegal(san) => write!(f, "{} is illegal here", san),
+            SanError::Ambiguous(san) => write!(f, "{} is ambiguous here", san),
+        }
+    }
+}
+
+fn letter_piece(letter: char) -> Option<PieceType> {
+    match letter {
+        'K' => Some(PieceType::King),
+        'Q' => Some(PieceType::Queen),
+        'B' => Some(PieceType::Bishop),
+        'N' => Some(PieceType::Knight),
+        'R' => Some(PieceType::Rook),
+        _ => None,
+    }
+}
+
+/// Finds the legal move in `position` that `san` describes. Check marks and annotations
+/// ("+", "#", "!", "?") are optional, and castling may be written with zeros.
+pub fn parse_san(position: &Position, san: &str) -> Result<Move, SanError> {
+    let syntax_error = || SanError::Syntax(san.to_string());
+    let text = san.trim_end_matches(&['+', '#', '!', '?'][..]);
+    let legal = legal_moves(position);
+
+    let candidates: Vec<Move> = if matches!(text, "O-O" | "0-0" | "O-O-O" | "0-0-0") {
+        let kingside = text.len() == 3;
+        legal
+            .into_iter()
+            .filter(|mv| position.is_castling(*mv) && (mv.to.1 == 6) == kingside)
+            .collect()
+    } else {
+        let mut text = text;
+
+        // Promotion, with or without the '='
+        let mut promotion = None;
+        if let Some(letter) = text.chars().last().filter(|c| c.is_ascii_uppercase()) {
+            promotion = Some(letter_piece(letter).ok_or_else(syntax_error)?);
+            text = text[..text.len() - 1].trim_end_matches('=');
+        }
+
+        // Destination square
+        if text.len() < 2 || !text.is_char_boundary(text.len() - 2) {
+            return Err(syntax_error());
+        }
+        let to = parse_square(&text[text.len() - 2..]).ok_or_else(syntax_error)?;
+        text = text[..text.len() - 2].trim_end_matches('x');
+
+        // Moving piece and optional starting file and rank
+        let mut chars = text.chars().peekable();
+        let piece_type = match chars.peek().and_then(|c| letter_piece(*c)) {
+            Some(piece_type) => {
+                chars.next();
+                piece_type
+            }
+            None => PieceType::Pawn,
+        };
+        let mut from_file = None;
+        let mut from_rank = None;
+        for c in chars {
+            match c {
+                'a'..='h' if from_file.is_none() && from_rank.is_none() => {
+                    from_file = Some(c as u8 - b'a')
+                }
+                '1'..='8' if from_rank.is_none() => from_rank = Some(c as u8 - b'1'),
+                _ => return Err(syntax_error()),
+            }
+        }
+
+        legal
+            .into_iter()
+            .filter(|mv| {
+                mv.to == to
+                    && mv.promotion == promotion
+                    && position.piece_at(mv.from).map(|piece| piece.piece_type) == Some(piece_type)
+                    && (from_file.is_none() || from_file == Some(mv.from.1))
+                    && (from_rank.is_none() || from_rank == Some(mv.from.0))
+                    && !position.is_castling(*mv)
+            })
+            .collect()
+    };
+
+    match candidates.len() {
+        1 => Ok(candidates[0]),
+        0 => Err(SanError::Illegal(san.to_string())),
+        _ => Err(SanError::Ambiguous(san.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::parse_fen;
+    use crate::game::Game;
+
+    fn mv(from: &str, to: &str) -> Move {
+        Move::new(parse_square(from).unwrap(), parse_square(to).unwrap())
+    }
+
+    fn promotion(from: &str, to: &str, piece_type: PieceType) -> Move {
+        Move::with_promotion(
+            parse_square(from).unwrap(),
+            parse_square(to).unwrap(),
+            piece_type,
+        )
+    }
+
+    /// Checks that `mv` is written `san` in `fen` and read back from it
+    fn assert_san(fen: &str, mv: Move, san: &str) {
+        let position = parse_fen(fen).unwrap();
+        assert_eq!(to_san(&position, mv), san, "in {}", fen);
+        assert_eq!(parse_san(&position, san), Ok(mv), "in {}", fen);
+    }
+
+    #[test]
+    fn disambiguation() {
+        // By file, by rank, and by both when neither is enough
+        let knights = "4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1";
+        assert_san(knights, mv("b1", "d2"), "Nbd2");
+        assert_san(knights, mv("f1", "d2"), "Nfd2");
+        assert_san(knights, mv("b1", "c3"), "Nc3");
+
+        let rooks = "4k3/8/8/R7/8/8/8/R3K3 w - - 0 1";
+        assert_san(rooks, mv("a1", "a3"), "R1a3");
+        assert_san(rooks, mv("a5", "a3"), "R5a3");
+
+        let queens = "2k5/8/8/8/4Q2Q/8/8/K6Q w - - 0 1";
+        assert_san(queens, mv("h4", "e1"), "Qh4e1");
+        assert_san(queens, mv("e4", "e1"), "Qee1");
+        assert_san(queens, mv("h1", "e1"), "Q1e1");
+
+        let position = parse_fen(knights).unwrap();
+        assert_eq!(
+            parse_san(&position, "Nd2"),
+            Err(SanError::Ambiguous(String::from("Nd2")))
+        );
+    }
+
+    #[test]
+    fn pawn_moves_and_promotion() {
+        let mut game = Game::new();
+        for san in ["e4", "d5", "exd5", "e5", "dxe6"].iter() {
+            let mv = parse_san(game.position(), san).unwrap();
+            assert_eq!(to_san(game.position(), mv), *san);
+            game.make_move(mv).unwrap();
+        }
+
+        let fen = "7k/1P6/8/8/8/8/8/4K3 w - - 0 1";
+        assert_san(fen, promotion("b7", "b8", PieceType::Queen), "b8=Q+");
+        assert_san(fen, promotion("b7", "b8", PieceType::Knight), "b8=N");
+        let position = parse_fen(fen).unwrap();
+        assert_eq!(
+            parse_san(&position, "b8Q"),
+            Ok(promotion("b7", "b8", PieceType::Queen))
+        );
+        assert_eq!(
+            parse_san(&position, "b8"),
+            Err(SanError::Illegal(String::from("b8")))
+        );
+        assert_eq!(
+            parse_san(&position, "b8=X"),
+            Err(SanError::Syntax(String::from("b8=X")))
+        );
+    }
+
+    #[test]
+    fn check_mate_and_castling() {
+        let mut game = Game::new();
+        for san in ["f3", "e5", "g4"].iter() {
+            game.make_move(parse_san(game.position(), san).unwrap())
+                .unwrap();
+        }
+        // The suffix is optional when reading
+        let mate = parse_san(game.position(), "Qh4").unwrap();
+        assert_eq!(to_san(game.position(), mate), "Qh4#");
+        assert_eq!(parse_san(game.position(), "Qh4#"), Ok(mate));
+
+        let castles = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_san(castles, mv("e1", "g1"), "O-O");
+        assert_san(castles, mv("e1", "c1"), "O-O-O");
+        let position = parse_fen(castles).unwrap();
+        assert_eq!(parse_san(&position, "0-0-0"), Ok(mv("e1", "c1")));
+        assert_eq!(
+            parse_san(&position, "Kg1"),
+            Err(SanError::Illegal(String::from("Kg1")))
+        );
+        assert_san("5k2/8/8/8/8/8/8/4K2R w K - 0 1", mv("e1", "g1"), "O-O+");
+
+        assert_eq!(
+            parse_san(&Position::initial(), "Ke2"),
+            Err(SanError::Illegal(String::from("Ke2")))
+        );
+        assert_eq!(
+            parse_san(&Position::initial(), "Zz9"),
+            Err(SanError::Syntax(String::from("Zz9")))
+        );
+    }
+
+    #[test]
+    fn long_algebraic_and_uci() {
+        let position = Position::initial();
+        assert_eq!(to_lan(&position, mv("g1", "f3")), "Ng1-f3");
+        assert_eq!(to_uci(mv("g1", "f3")), "g1f3");
+        assert_eq!(parse_uci(&position, "g1f3"), Some(mv("g1", "f3")));
+        assert_eq!(parse_uci(&position, "g1g3"), None);
+
+        let position = parse_fen("3r3k/4P3/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let capture = promotion("e7", "d8", PieceType::Queen);
+        assert_eq!(to_lan(&position, capture), "e7xd8=Q+");
+        assert_eq!(to_uci(capture), "e7d8q");
+        assert_eq!(parse_uci(&position, "e7d8q"), Some(capture));
+    }
+}
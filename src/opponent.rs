@@ -0,0 +1,177 @@
+use crate::board::PlayMove;
+use crate::clock::GameClock;
+use crate::engine::{SearchInfo, SearchLimits, Searcher};
+use crate::external::ExternalEngine;
+use crate::game::{Game, PieceColor, Position};
+use crate::replay::Replay;
+use crate::transposition::TranspositionTable;
+use bevy::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Share of its remaining time the engine spends on a move when playing on the clock
+const CLOCK_FRACTION: u32 = 30;
+
+/// Which side, if any, the built-in engine plays, and how long it may think
+pub struct EngineOpponent {
+    pub color: Option<PieceColor>,
+    pub limits: SearchLimits,
+    /// Kept from move to move, so the engine remembers what it found out thinking before
+    table: Arc<Mutex<TranspositionTable>>,
+}
+
+impl Default for EngineOpponent {
+    fn default() -> Self {
+        Self {
+            color: None,
+            limits: SearchLimits {
+                movetime: Some(Duration::from_secs(1)),
+                ..SearchLimits::default()
+            },
+            table: Arc::default(),
+        }
+    }
+}
+
+impl EngineOpponent {
+    pub fn plays(&self, color: PieceColor) -> bool {
+        self.color == Some(color)
+    }
+
+    /// Size of the transposition table in megabytes
+    pub fn hash_size(&self) -> usize {
+        self.table.lock().unwrap().megabytes()
+    }
+
+    /// Replaces the transposition table with an empty one of `megabytes`, which a search
+    /// going on carries on with
+    pub fn set_hash_size(&mut self, megabytes: usize) {
+        self.table.lock().unwrap().resize(megabytes);
+    }
+
+    /// Forgets everything the engine found out, for a new game
+    pub fn clear_hash(&mut self) {
+        self.table.lock().unwrap().clear();
+    }
+
+    /// The configured limits, shortened when the clock is running low
+    pub fn limits(&self, clock: &GameClock) -> SearchLimits {
+        let mut limits = self.limits;
+        if let (Some(clock), Some(color)) = (clock.0.as_ref(), self.color) {
+            let budget = clock.remaining(color) / CLOCK_FRACTION;
+            limits.movetime = Some(
+                limits
+                    .movetime
+                    .map_or(budget, |movetime| movetime.min(budget)),
+            );
+        }
+        limits
+    }
+}
+
+/// A search running on its own thread so rendering goes on meanwhile
+struct Search {
+    /// Where the search sends its result when done
+    receiver: Mutex<Receiver<SearchInfo>>,
+    stop: Arc<AtomicBool>,
+    /// Position searched, to check the game is still there and to name the move found
+    position: Position,
+}
+
+impl Search {
+    fn cancel(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The engine's search in progress, if any
+#[derive(Default)]
+pub struct EngineThinking(Option<Search>);
+
+impl EngineThinking {
+    pub fn is_thinking(&self) -> bool {
+        self.0.is_some()
+    }
+}
+
+/// Whenever the game changes, cancel the search for the old position, and start a new one
+/// if it is the engine's turn. An external engine, if any, plays instead.
+fn start_search(
+    game: ChangedRes<Game>,
+    opponent: Res<EngineOpponent>,
+    external: Res<ExternalEngine>,
+    replay: Res<Replay>,
+    clock: Res<GameClock>,
+    mut thinking: ResMut<EngineThinking>,
+) {
+    if let Some(search) = thinking.0.take() {
+        search.cancel();
+    }
+    if replay.is_active()
+        || external.is_running()
+        || !opponent.plays(game.turn())
+        || game.outcome().is_some()
+    {
+        return;
+    }
+
+    let mut searcher = Searcher::with_table(opponent.table.clone());
+    let stop = searcher.stop_flag();
+    let (sender, receiver) = channel();
+    let position = game.position().clone();
+    let history: Vec<_> = (0..game.moves().len())
+        .map(|ply| game.position_at(ply).key())
+        .collect();
+    let limits = opponent.limits(&clock);
+    let searched = position.clone();
+    thread::spawn(move || {
+        let info = searcher.search(&searched, &history, limits, |_| {});
+        // Nobody listens anymore if the search was canceled
+        let _ = sender.send(info);
+    });
+
+    thinking.0 = Some(Search {
+        receiver: Mutex::new(receiver),
+        stop,
+        position,
+    });
+}
+
+/// Play the engine's move like a human move would be played once its search is done
+fn finish_search(
+    game: Res<Game>,
+    mut thinking: ResMut<EngineThinking>,
+    mut play_move_events: ResMut<Events<PlayMove>>,
+) {
+    let info = match &thinking.0 {
+        Some(search) => match search.receiver.lock().unwrap().try_recv() {
+            Ok(info) => info,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => SearchInfo::default(),
+        },
+        None => return,
+    };
+
+    if let Some(search) = thinking.0.take() {
+        // The game moved on before `start_search` got to cancel this one
+        if search.position != *game.position() {
+            return;
+        }
+        if let Some(mv) = info.best_move() {
+            play_move_events.send(PlayMove(mv));
+        }
+    }
+}
+
+pub struct OpponentPlugin;
+impl Plugin for OpponentPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<EngineOpponent>()
+            .init_resource::<EngineThinking>()
+            .add_system(start_search.system())
+            .add_system(finish_search.system());
+    }
+}
@@ -0,0 +1,123 @@
+//! Move generation checks: perft counts the leaf nodes of the legal move tree to a given
+//! depth, which can be compared with published numbers, and divide splits that count by
+//! first move to find where two generators disagree.
+
+use crate::game::{Move, Position};
+
+/// Number of move sequences `depth` half moves long from `position`
+pub fn perft(position: &Position, depth: u32) -> u64 {
+    count(&mut position.clone(), depth)
+}
+
+/// `perft`, making and taking back the moves on `position`
+fn count(position: &mut Position, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let moves = position.legal_moves();
+    // The moves themselves are the leaves, no need to play them
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    moves
+        .iter()
+        .map(|mv| {
+            let effects = position.apply_move(*mv);
+            let nodes = count(position, depth - 1);
+            position.unmake_move(*mv, &effects);
+            nodes
+        })
+        .sum()
+}
+
+/// Perft of the position after each legal move, the counts adding up to `perft(depth)`
+pub fn divide(position: &Position, depth: u32) -> Vec<(Move, u64)> {
+    position
+        .legal_moves()
+        .into_iter()
+        .map(|mv| {
+            let mut child = position.clone();
+            child.apply_move(mv);
+            (mv, perft(&child, depth.saturating_sub(1)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::{parse_fen, STARTING_FEN};
+
+    // Positions and node counts from https://www.chessprogramming.org/Perft_Results
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    const POSITION_3: &str = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+    const POSITION_4: &str = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+    const POSITION_5: &str = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+    const POSITION_6: &str =
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
+
+    fn assert_perft(fen: &str, expected: &[u64]) {
+        let position = parse_fen(fen).unwrap();
+        for (depth, nodes) in expected.iter().enumerate() {
+            let depth = depth as u32 + 1;
+            assert_eq!(
+                perft(&position, depth),
+                *nodes,
+                "perft({}) of {}",
+                depth,
+                fen
+            );
+        }
+    }
+
+    #[test]
+    fn initial_position() {
+        assert_perft(STARTING_FEN, &[20, 400, 8_902, 197_281]);
+    }
+
+    #[test]
+    fn kiwipete() {
+        assert_perft(KIWIPETE, &[48, 2_039, 97_862]);
+    }
+
+    #[test]
+    fn position_3() {
+        assert_perft(POSITION_3, &[14, 191, 2_812, 43_238]);
+    }
+
+    #[test]
+    fn position_4() {
+        assert_perft(POSITION_4, &[6, 264, 9_467]);
+    }
+
+    #[test]
+    fn position_5() {
+        assert_perft(POSITION_5, &[44, 1_486, 62_379]);
+    }
+
+    #[test]
+    fn position_6() {
+        assert_perft(POSITION_6, &[46, 2_079, 89_890]);
+    }
+
+    /// Millions of nodes each, run with `cargo test --release -- --ignored`
+    #[test]
+    #[ignore]
+    fn deep() {
+        assert_perft(STARTING_FEN, &[20, 400, 8_902, 197_281, 4_865_609]);
+        assert_perft(KIWIPETE, &[48, 2_039, 97_862, 4_085_603]);
+        assert_perft(POSITION_3, &[14, 191, 2_812, 43_238, 674_624, 11_030_083]);
+        assert_perft(POSITION_4, &[6, 264, 9_467, 422_333]);
+        assert_perft(POSITION_5, &[44, 1_486, 62_379, 2_103_487]);
+        assert_perft(POSITION_6, &[46, 2_079, 89_890, 3_894_594]);
+    }
+
+    #[test]
+    fn divide_adds_up() {
+        let position = parse_fen(KIWIPETE).unwrap();
+        let divided = divide(&position, 3);
+        assert_eq!(divided.len(), 48);
+        assert_eq!(divided.iter().map(|(_, nodes)| nodes).sum::<u64>(), 97_862);
+    }
+}
@@ -0,0 +1,550 @@
+//! Portable Game Notation: writing games out with the Seven Tag Roster and SAN movetext, and
+//! reading them back, several games per file if need be.
+
+use crate::fen::{parse_fen, to_fen, FenError, STARTING_FEN};
+use crate::game::{Game, GameOver, GameOverReason, GameResult, Move, PieceColor, Position};
+use crate::notation::{move_number, parse_san, to_san, SanError};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// PGN export lines are kept under 80 characters
+const MAX_LINE_LENGTH: usize = 79;
+
+/// The Seven Tag Roster, except the result which comes from the game itself
+#[derive(Debug, Clone)]
+pub struct PgnHeaders {
+    pub event: String,
+    pub site: String,
+    pub date: String,
+    pub round: String,
+    pub white: String,
+    pub black: String,
+}
+
+impl Default for PgnHeaders {
+    fn default() -> Self {
+        Self {
+            event: String::from("Casual game"),
+            site: String::from("Rustic Chess"),
+            date: today(),
+            round: String::from("-"),
+            white: String::from("?"),
+            black: String::from("?"),
+        }
+    }
+}
+
+/// Today's UTC date in the PGN "YYYY.MM.DD" format
+pub fn today() -> String {
+    let (year, month, day, _) = civil_time(seconds_since_epoch());
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+fn seconds_since_epoch() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Splits a unix timestamp into UTC `(year, month, day, seconds into the day)`
+fn civil_time(timestamp: u64) -> (i64, u32, u32, u64) {
+    // Howard Hinnant's days-to-civil algorithm
+    let days = (timestamp / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day, timestamp % 86_400)
+}
+
+/// PGN result token: "1-0", "0-1", "1/2-1/2", or "*" while the game is still going
+pub fn result_token(outcome: Option<GameOver>) -> &'static str {
+    match outcome.map(|game_over| game_over.result) {
+        Some(GameResult::WhiteWins) => "1-0",
+        Some(GameResult::BlackWins) => "0-1",
+        Some(GameResult::Draw) => "1/2-1/2",
+        None => "*",
+    }
+}
+
+fn reason_comment(reason: GameOverReason) -> &'static str {
+    match reason {
+        GameOverReason::Checkmate => "Checkmate",
+        GameOverReason::Stalemate => "Draw by stalemate",
+        GameOverReason::InsufficientMaterial => "Draw by insufficient material",
+        GameOverReason::FiftyMoveRule => "Draw claimed by the fifty-move rule",
+        GameOverReason::SeventyFiveMoveRule => "Draw by the seventy-five-move rule",
+        GameOverReason::ThreefoldRepetition => "Draw claimed by threefold repetition",
+        GameOverReason::FivefoldRepetition => "Draw by fivefold repetition",
+        GameOverReason::Timeout => "Lost on time",
+        GameOverReason::TimeoutVsInsufficientMaterial => {
+            "Draw by timeout against insufficient material"
+        }
+    }
+}
+
+fn escape_tag_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Writes `game` as a PGN export with `headers`. Games that didn't start from the initial
+/// position get `SetUp` and `FEN` tags.
+pub fn to_pgn(game: &Game, headers: &PgnHeaders) -> String {
+    let outcome = game.outcome();
+    let result = result_token(outcome);
+
+    let mut pgn = String::new();
+    let mut tags = vec![
+        ("Event", headers.event.clone()),
+        ("Site", headers.site.clone()),
+        ("Date", headers.date.clone()),
+        ("Round", headers.round.clone()),
+        ("White", headers.white.clone()),
+        ("Black", headers.black.clone()),
+        ("Result", result.to_string()),
+    ];
+    let starting_fen = to_fen(game.starting_position());
+    if starting_fen != STARTING_FEN {
+        tags.push(("SetUp", String::from("1")));
+        tags.push(("FEN", starting_fen));
+    }
+    for (name, value) in tags {
+        pgn.push_str(&format!("[{} \"{}\"]\n", name, escape_tag_value(&value)));
+    }
+    pgn.push('\n');
+
+    // Movetext tokens, replaying the game to write each move in SAN
+    let mut tokens = Vec::new();
+    let mut position = game.starting_position().clone();
+    for (i, mv) in game.moves().iter().enumerate() {
+        if position.turn() == PieceColor::White || i == 0 {
+            tokens.push(move_number(&position));
+        }
+        tokens.push(to_san(&position, *mv));
+        position.apply_move(*mv);
+    }
+    if let Some(game_over) = outcome {
+        tokens.push(format!("{{{}}}", reason_comment(game_over.reason)));
+    }
+    tokens.push(result.to_string());
+
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > MAX_LINE_LENGTH {
+            pgn.push_str(&line);
+            pgn.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    pgn.push_str(&line);
+    pgn.push_str("\n\n");
+    pgn
+}
+
+/// Saves `game` as a new file in `directory`, creating it if needed, and returns its path
+pub fn save_pgn(game: &Game, headers: &PgnHeaders, directory: &Path) -> io::Result<PathBuf> {
+    std::fs::create_dir_all(directory)?;
+
+    let (year, month, day, seconds) = civil_time(seconds_since_epoch());
+    let name = format!(
+        "game-{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    let mut path = directory.join(format!("{}.pgn", name));
+    let mut copy = 1;
+    while path.exists() {
+        path = directory.join(format!("{}-{}.pgn", name, copy));
+        copy += 1;
+    }
+
+    std::fs::write(&path, to_pgn(game, headers))?;
+    Ok(path)
+}
+
+// Reading ====================================================================================== //
+/// A game read from PGN. Its moves have already been checked against the rules.
+#[derive(Debug, Clone)]
+pub struct PgnGame {
+    /// Tag pairs in the order they appeared
+    pub tags: Vec<(String, String)>,
+    pub starting_position: Position,
+    pub moves: Vec<Move>,
+    /// Result token that closed the movetext, "*" if there was none
+    pub result: String,
+}
+
+impl PgnGame {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The game as it stood after its first `ply` half moves. Moves past an automatic draw
+    /// are left out.
+    pub fn game_at(&self, ply: usize) -> Game {
+        let mut game = Game::from_position(self.starting_position.clone());
+        for mv in self.moves.iter().take(ply) {
+            if game.make_move(*mv).is_err() {
+                break;
+            }
+        }
+        game
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PgnError {
+    /// Broken tag pair or unterminated comment or variation
+    Syntax { game: usize, message: String },
+    /// The FEN tag doesn't hold a valid position
+    Fen { game: usize, error: FenError },
+    /// A move that doesn't resolve to exactly one legal move
+    Move {
+        game: usize,
+        move_number: String,
+        error: SanError,
+    },
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PgnError::Syntax { game, message } => write!(f, "game {}: {}", game, message),
+            PgnError::Fen { game, error } => write!(f, "game {}: invalid FEN tag, {}", game, error),
+            PgnError::Move {
+                game,
+                move_number,
+                error,
+            } => write!(f, "game {}, move {} {}", game, move_number, error),
+        }
+    }
+}
+
+/// The game being read: tags first, then moves replayed on `position`
+#[derive(Default)]
+struct GameReader {
+    tags: Vec<(String, String)>,
+    start: Option<Position>,
+    position: Position,
+    moves: Vec<Move>,
+}
+
+impl GameReader {
+    fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.start.is_none()
+    }
+
+    /// Sets up the starting position the first time a move shows up
+    fn start(&mut self, game: usize) -> Result<(), PgnError> {
+        if self.start.is_none() {
+            let fen = self
+                .tags
+                .iter()
+                .find(|(tag, _)| tag == "FEN")
+                .map(|(_, value)| value.as_str());
+            let start = match fen {
+                Some(fen) => parse_fen(fen).map_err(|error| PgnError::Fen { game, error })?,
+                None => Position::initial(),
+            };
+            self.position = start.clone();
+            self.start = Some(start);
+        }
+        Ok(())
+    }
+
+    fn play(&mut self, game: usize, san: &str) -> Result<(), PgnError> {
+        self.start(game)?;
+        let mv = parse_san(&self.position, san).map_err(|error| PgnError::Move {
+            game,
+            move_number: move_number(&self.position),
+            error,
+        })?;
+        self.position.apply_move(mv);
+        self.moves.push(mv);
+        Ok(())
+    }
+
+    fn finish(mut self, game: usize, result: &str) -> Result<PgnGame, PgnError> {
+        self.start(game)?;
+        Ok(PgnGame {
+            tags: self.tags,
+            starting_position: self.start.unwrap_or_default(),
+            moves: self.moves,
+            result: result.to_string(),
+        })
+    }
+}
+
+/// Reads every game in `text`. Comments, NAGs and variations are skipped.
+pub fn parse_pgn(text: &str) -> Result<Vec<PgnGame>, PgnError> {
+    let mut games = Vec::new();
+    let mut reader = GameReader::default();
+    let mut chars = text.chars().peekable();
+    let syntax = |game: usize, message: &str| PgnError::Syntax {
+        game,
+        message: message.to_string(),
+    };
+
+    while let Some(c) = chars.next() {
+        let game = games.len() + 1;
+        match c {
+            c if c.is_whitespace() => {}
+            '[' => {
+                // A tag after some moves starts the next game, even without a result
+                if reader.start.is_some() {
+                    games.push(std::mem::take(&mut reader).finish(game, "*")?);
+                }
+                let mut pair = String::new();
+                let mut in_quotes = false;
+                loop {
+                    match chars.next() {
+                        Some('\\') if in_quotes => pair.extend(chars.next()),
+                        Some('"') => {
+                            in_quotes = !in_quotes;
+                            pair.push('"');
+                        }
+                        Some(']') if !in_quotes => break,
+                        Some(c) => pair.push(c),
+                        None => return Err(syntax(game, "unterminated tag pair")),
+                    }
+                }
+                let pair = pair.trim();
+                let name_end = pair
+                    .find(char::is_whitespace)
+                    .ok_or_else(|| syntax(game, "tag pair without a value"))?;
+                let value = pair[name_end..].trim();
+                if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+                    return Err(syntax(game, "tag value must be quoted"));
+                }
+                reader.tags.push((
+                    pair[..name_end].to_string(),
+                    value[1..value.len() - 1].to_string(),
+                ));
+            }
+            '{' => {
+                if !chars.by_ref().any(|c| c == '}') {
+                    return Err(syntax(game, "unterminated comment"));
+                }
+            }
+            ';' => {
+                // Rest-of-line comment
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '(' => {
+                let mut depth = 1;
+                while depth > 0 {
+                    match chars.next() {
+                        Some('(') => depth += 1,
+                        Some(')') => depth -= 1,
+                        Some('{') => {
+                            if !chars.by_ref().any(|c| c == '}') {
+                                return Err(syntax(game, "unterminated comment"));
+                            }
+                        }
+                        Some(_) => {}
+                        None => return Err(syntax(game, "unterminated variation")),
+                    }
+                }
+            }
+            _ => {
+                let mut token = c.to_string();
+                while let Some(c) = chars.peek() {
+                    if c.is_whitespace() || "[]{}();".contains(*c) {
+                        break;
+                    }
+                    token.push(*c);
+                    chars.next();
+                }
+
+                match token.as_str() {
+                    "1-0" | "0-1" | "1/2-1/2" | "*" => {
+                        games.push(std::mem::take(&mut reader).finish(game, &token)?);
+                    }
+                    _ if token.starts_with('$') => {}
+                    _ => {
+                        // Move numbers may be glued to the move, as in "12.e4"
+                        let san = if token.starts_with(|c: char| c.is_ascii_digit())
+                            && token.contains('.')
+                        {
+                            token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.')
+                        } else {
+                            token.as_str()
+                        };
+                        if !san.is_empty() {
+                            reader.play(game, san)?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if !reader.is_empty() {
+        let game = games.len() + 1;
+        games.push(reader.finish(game, "*")?);
+    }
+    Ok(games)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(game: &mut Game, moves: &str) {
+        for san in moves.split_whitespace() {
+            let mv = parse_san(game.position(), san).unwrap();
+            game.make_move(mv).unwrap();
+        }
+    }
+
+    fn headers() -> PgnHeaders {
+        PgnHeaders {
+            white: String::from("Anderssen"),
+            black: String::from("Kieseritzky \"Lionel\""),
+            ..PgnHeaders::default()
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut game = Game::new();
+        play(&mut game, "e4 e5 Bc4 Nc6 Qh5 Nf6 Qxf7#");
+        let pgn = to_pgn(&game, &headers());
+        assert!(pgn.contains("[Black \"Kieseritzky \\\"Lionel\\\"\"]"));
+        assert!(pgn.ends_with("4. Qxf7# {Checkmate} 1-0\n\n"));
+
+        let games = parse_pgn(&pgn).unwrap();
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].moves, game.moves());
+        assert_eq!(games[0].result, "1-0");
+        assert_eq!(games[0].tag("Black"), Some("Kieseritzky \"Lionel\""));
+        assert_eq!(games[0].tag("FEN"), None);
+        assert_eq!(games[0].game_at(usize::MAX).outcome(), game.outcome());
+
+        // From a set up position, with black to move first and lines long enough to wrap
+        let start = parse_fen("4k3/8/8/8/8/8/4P3/R3K3 b Q - 0 30").unwrap();
+        let mut game = Game::from_position(start.clone());
+        play(
+            &mut game,
+            "Kd7 O-O-O+ Kc6 e4 Kb5 e5 Kc4 e6 Kb3 e7 Ka2 e8=Q Kb3 Qb5+ Kc3",
+        );
+        let pgn = to_pgn(&game, &headers());
+        assert!(pgn.contains("[SetUp \"1\"]"));
+        assert!(pgn.contains("30... Kd7 31. O-O-O+"));
+        assert!(pgn.lines().all(|line| line.len() <= MAX_LINE_LENGTH));
+
+        let games = parse_pgn(&pgn).unwrap();
+        assert_eq!(games[0].starting_position, start);
+        assert_eq!(games[0].moves, game.moves());
+        assert_eq!(games[0].result, "*");
+        assert_eq!(to_pgn(&games[0].game_at(usize::MAX), &headers()), pgn);
+    }
+
+    #[test]
+    fn several_games() {
+        let text = "[Event \"First\"]\n\n1. e4 e5 1-0\n\n\
+                    [Event \"Second\"]\n\n1. d4\n\n\
+                    [Event \"Third\"]\n\n1. c4 c5 2. Nc3 1/2-1/2\n\n\
+                    1. Nf3 *";
+        let games = parse_pgn(text).unwrap();
+        let summary: Vec<(Option<&str>, usize, &str)> = games
+            .iter()
+            .map(|game| (game.tag("Event"), game.moves.len(), game.result.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Some("First"), 2, "1-0"),
+                (Some("Second"), 1, "*"),
+                (Some("Third"), 3, "1/2-1/2"),
+                (None, 1, "*"),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_comments_variations_and_nags() {
+        let text = "[Event \"Annotated\"]\n\
+                    1. e4 {Best by test} e5 $1 2.Nf3 (2. f4 exf4 (2... d5 {Falkbeer}) 3. Nf3)\n\
+                    2... Nc6 ; the main line\n\
+                    3. Bb5!? a6?! *";
+        let games = parse_pgn(text).unwrap();
+        let mut game = Game::new();
+        play(&mut game, "e4 e5 Nf3 Nc6 Bb5 a6");
+        assert_eq!(games[0].moves, game.moves());
+
+        assert_eq!(
+            parse_pgn("1. e4 {never closed e5 *").unwrap_err(),
+            PgnError::Syntax {
+                game: 1,
+                message: String::from("unterminated comment"),
+            }
+        );
+        assert_eq!(
+            parse_pgn("1. e4 (1. d4 d5 *").unwrap_err(),
+            PgnError::Syntax {
+                game: 1,
+                message: String::from("unterminated variation"),
+            }
+        );
+    }
+
+    #[test]
+    fn errors_name_the_game_and_move() {
+        let text = "1. e4 e5 *\n\n1. e4 e5 2. Ke3 *";
+        assert_eq!(
+            parse_pgn(text).unwrap_err(),
+            PgnError::Move {
+                game: 2,
+                move_number: String::from("2."),
+                error: SanError::Illegal(String::from("Ke3")),
+            }
+        );
+
+        // Both knights can go to d2
+        assert_eq!(
+            parse_pgn("1. Nf3 a6 2. d3 a5 3. Nd2 *").unwrap_err(),
+            PgnError::Move {
+                game: 1,
+                move_number: String::from("3."),
+                error: SanError::Ambiguous(String::from("Nd2")),
+            }
+        );
+        assert!(parse_pgn("1. Nf3 a6 2. d3 a5 3. Nbd2 *").is_ok());
+
+        assert_eq!(
+            parse_pgn("[FEN \"8/8/8/8/8/8/8/8 w - - 0 1\"]\n\n*").unwrap_err(),
+            PgnError::Fen {
+                game: 1,
+                error: FenError::BadKings,
+            }
+        );
+    }
+}
@@ -1,21 +1,8 @@
+use crate::game::Game;
 use bevy::prelude::*;
 
 // Piece attributes ============================================================================= //
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum PieceColor {
-    White,
-    Black,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum PieceType {
-    King,
-    Queen,
-    Bishop,
-    Knight,
-    Rook,
-    Pawn,
-}
+pub use crate::game::{BoardPiece, PieceColor, PieceType};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Piece {
@@ -26,124 +13,12 @@ pub struct Piece {
     pub y: u8,
 }
 
-impl Piece{
-    pub fn is_move_valid(&self, new_position: (u8, u8), pieces: Vec<Piece>) -> bool{
-        if color_of_square(new_position, &pieces) == Some(self.color){
-            return false;
-        }
-        match self.piece_type {
-            PieceType::King => {
-                // castle
-                // TODO check for corresponding rook
-                (is_path_empty((self.x, self.y), new_position, &pieces)
-                && (new_position.0 == 0 && new_position.1 == 6)
-                || (new_position.0 == 0 && new_position.1 == 2)
-                || (new_position.0 == 7 && new_position.1 == 6)
-                || (new_position.0 == 7 && new_position.1 == 2))
-
-                // horizontal
-                || ((self.x as i8 - new_position.0 as i8).abs() == 1
-                && (self.y == new_position.1))
-
-                // vertical
-                || ((self.y as i8 - new_position.1 as i8).abs() == 1
-                && (self.x == new_position.0))
-
-                // diagonal
-                || ((self.x as i8 - new_position.0 as i8).abs() == 1
-                && (self.y as i8 - new_position.1 as i8).abs() == 1)
-            }
-
-            PieceType::Queen => {
-                is_path_empty((self.x, self.y), new_position, &pieces)
-                    && ((self.x as i8 - new_position.0 as i8).abs()
-                    == (self.y as i8 - new_position.1 as i8).abs()
-                    || ((self.x == new_position.0 && self.y != new_position.1)
-                    || (self.y == new_position.1 && self.x != new_position.0)))
-            }
-            PieceType::Bishop => {
-                is_path_empty((self.x, self.y), new_position, &pieces)
-                    && (self.x as i8 - new_position.0 as i8).abs()
-                    == (self.y as i8 - new_position.1 as i8).abs()
-            }
-            PieceType::Knight => {
-                ((self.x as i8 - new_position.0 as i8).abs() == 2
-                    && (self.y as i8 - new_position.1 as i8).abs() == 1)
-                    || ((self.x as i8 - new_position.0 as i8).abs() == 1
-                    && (self.y as i8 - new_position.1 as i8).abs() == 2)
-            }
-            PieceType::Rook => {
-                is_path_empty((self.x, self.y), new_position, &pieces)
-                    && ((self.x == new_position.0 && self.y != new_position.1)
-                    || (self.y == new_position.1 && self.x != new_position.0))
-            }
-            PieceType::Pawn => {
-                if self.color == PieceColor::White {
-                    // Normal move
-                    if new_position.0 as i8 - self.x as i8 == 1 && (self.y == new_position.1) {
-                        if color_of_square(new_position, &pieces).is_none() {
-                            return true;
-                        }
-                    }
-
-                    // Move 2 squares
-                    if self.x == 1
-                        && new_position.0 as i8 - self.x as i8 == 2
-                        && (self.y == new_position.1)
-                        && is_path_empty((self.x, self.y), new_position, &pieces)
-                    {
-                        if color_of_square(new_position, &pieces).is_none() {
-                            return true;
-                        }
-                    }
-
-                    // Take piece
-                    if new_position.0 as i8 - self.x as i8 == 1
-                        && (self.y as i8 - new_position.1 as i8).abs() == 1
-                    {
-                        if color_of_square(new_position, &pieces) == Some(PieceColor::Black) {
-                            return true;
-                        }
-                    }
-                } else {
-                    // Normal move
-                    if new_position.0 as i8 - self.x as i8 == -1 && (self.y == new_position.1) {
-                        if color_of_square(new_position, &pieces).is_none() {
-                            return true;
-                        }
-                    }
-
-                    // Move 2 squares
-                    if self.x == 6
-                        && new_position.0 as i8 - self.x as i8 == -2
-                        && (self.y == new_position.1)
-                        && is_path_empty((self.x, self.y), new_position, &pieces)
-                    {
-                        if color_of_square(new_position, &pieces).is_none() {
-                            return true;
-                        }
-                    }
-
-                    // Take piece
-                    if new_position.0 as i8 - self.x as i8 == -1
-                        && (self.y as i8 - new_position.1 as i8).abs() == 1
-                    {
-                        if color_of_square(new_position, &pieces) == Some(PieceColor::White) {
-                            return true;
-                        }
-                    }
-                }
-                false
-            }
-        }
-    }
-}
-
 // Pieces plugin ================================================================================ //
 pub struct PiecesPlugin;
 impl Plugin for PiecesPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_startup_system(create_pieces.system())
+        app.init_resource::<PieceAssets>()
+            .add_startup_system(create_pieces.system())
             .add_system(move_pieces.system());
     }
 }
@@ -313,7 +188,7 @@ fn spawn_bishop(
         });
 }
 
-pub fn spawn_rook(
+fn spawn_rook(
     commands: &mut Commands,
     material: Handle<StandardMaterial>,
     piece_color: PieceColor,
@@ -380,34 +255,107 @@ fn spawn_pawn(
         });
 }
 
+// piece assets ================================================================================= //
+/// Meshes and materials shared by every piece, so pieces can be spawned after startup too
+pub struct PieceAssets {
+    king: Handle<Mesh>,
+    king_cross: Handle<Mesh>,
+    pawn: Handle<Mesh>,
+    knight_1: Handle<Mesh>,
+    knight_2: Handle<Mesh>,
+    rook: Handle<Mesh>,
+    bishop: Handle<Mesh>,
+    queen: Handle<Mesh>,
+    white_material: Handle<StandardMaterial>,
+    black_material: Handle<StandardMaterial>,
+}
+
+impl FromResources for PieceAssets {
+    fn from_resources(resources: &Resources) -> Self {
+        let asset_server = resources.get::<AssetServer>().unwrap();
+        let mut materials = resources.get_mut::<Assets<StandardMaterial>>().unwrap();
+
+        // Load all the meshes and add some materials
+        PieceAssets {
+            king: asset_server.load("models/chess_kit/pieces.glb#Mesh0/Primitive0"),
+            king_cross: asset_server.load("models/chess_kit/pieces.glb#Mesh1/Primitive0"),
+            pawn: asset_server.load("models/chess_kit/pieces.glb#Mesh2/Primitive0"),
+            knight_1: asset_server.load("models/chess_kit/pieces.glb#Mesh3/Primitive0"),
+            knight_2: asset_server.load("models/chess_kit/pieces.glb#Mesh4/Primitive0"),
+            rook: asset_server.load("models/chess_kit/pieces.glb#Mesh5/Primitive0"),
+            bishop: asset_server.load("models/chess_kit/pieces.glb#Mesh6/Primitive0"),
+            queen: asset_server.load("models/chess_kit/pieces.glb#Mesh7/Primitive0"),
+            white_material: materials.add(Color::rgb(1., 0.8, 0.8).into()),
+            black_material: materials.add(Color::rgb(0., 0.2, 0.2).into()),
+        }
+    }
+}
+
+/// Spawns the entity for `piece` on `position` with the matching spawn function
+pub fn spawn_piece(
+    commands: &mut Commands,
+    assets: &PieceAssets,
+    piece: BoardPiece,
+    position: (u8, u8),
+) {
+    let material = match piece.color {
+        PieceColor::White => assets.white_material.clone(),
+        PieceColor::Black => assets.black_material.clone(),
+    };
+    match piece.piece_type {
+        PieceType::King => spawn_king(
+            commands,
+            material,
+            piece.color,
+            assets.king.clone(),
+            assets.king_cross.clone(),
+            position,
+        ),
+        PieceType::Queen => spawn_queen(
+            commands,
+            material,
+            piece.color,
+            assets.queen.clone(),
+            position,
+        ),
+        PieceType::Bishop => spawn_bishop(
+            commands,
+            material,
+            piece.color,
+            assets.bishop.clone(),
+            position,
+        ),
+        PieceType::Knight => spawn_knight(
+            commands,
+            material,
+            piece.color,
+            assets.knight_1.clone(),
+            assets.knight_2.clone(),
+            position,
+        ),
+        PieceType::Rook => spawn_rook(
+            commands,
+            material,
+            piece.color,
+            assets.rook.clone(),
+            position,
+        ),
+        PieceType::Pawn => spawn_pawn(
+            commands,
+            material,
+            piece.color,
+            assets.pawn.clone(),
+            position,
+        ),
+    }
+}
+
 // create pieces ================================================================================ //
 fn create_pieces(
     commands: &mut Commands,
-    asset_server: Res<AssetServer>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    game: Res<Game>,
+    assets: Res<PieceAssets>,
 ){
-    // Load all the meshes
-    let king_handle: Handle<Mesh> =
-        asset_server.load("models/chess_kit/pieces.glb#Mesh0/Primitive0");
-    let king_cross_handle: Handle<Mesh> =
-        asset_server.load("models/chess_kit/pieces.glb#Mesh1/Primitive0");
-    let pawn_handle: Handle<Mesh> =
-        asset_server.load("models/chess_kit/pieces.glb#Mesh2/Primitive0");
-    let knight_1_handle: Handle<Mesh> =
-        asset_server.load("models/chess_kit/pieces.glb#Mesh3/Primitive0");
-    let knight_2_handle: Handle<Mesh> =
-        asset_server.load("models/chess_kit/pieces.glb#Mesh4/Primitive0");
-    let rook_handle: Handle<Mesh> =
-        asset_server.load("models/chess_kit/pieces.glb#Mesh5/Primitive0");
-    let bishop_handle: Handle<Mesh> =
-        asset_server.load("models/chess_kit/pieces.glb#Mesh6/Primitive0");
-    let queen_handle: Handle<Mesh> =
-        asset_server.load("models/chess_kit/pieces.glb#Mesh7/Primitive0");
-
-    // Add some materials
-    let white_material = materials.add(Color::rgb(1., 0.8, 0.8).into());
-    let black_material = materials.add(Color::rgb(0., 0.2, 0.2).into());
-
     commands
         // Spawn parent entity
         .spawn(PbrBundle {
@@ -415,144 +363,9 @@ fn create_pieces(
             ..Default::default()
         });
 
-    spawn_rook(
-        commands,
-        white_material.clone(),
-        PieceColor::White,
-        rook_handle.clone(),
-        (0,0),
-    );
-    spawn_knight(
-        commands,
-        white_material.clone(),
-        PieceColor::White,
-        knight_1_handle.clone(),
-        knight_2_handle.clone(),
-        (0,1),
-    );
-    spawn_bishop(
-        commands,
-        white_material.clone(),
-        PieceColor::White,
-        bishop_handle.clone(),
-        (0,2)
-    );
-    spawn_queen(
-        commands,
-        white_material.clone(),
-        PieceColor::White,
-        queen_handle.clone(),
-        (0,3)
-    );
-    spawn_king(
-        commands,
-        white_material.clone(),
-        PieceColor::White,
-        king_handle.clone(),
-        king_cross_handle.clone(),
-        (0,4)
-    );
-    spawn_bishop(
-        commands,
-        white_material.clone(),
-        PieceColor::White,
-        bishop_handle.clone(),
-        (0,5)
-    );
-    spawn_knight(
-        commands,
-        white_material.clone(),
-        PieceColor::White,
-        knight_1_handle.clone(),
-        knight_2_handle.clone(),
-        (0,6),
-    );
-    spawn_rook(
-        commands,
-        white_material.clone(),
-        PieceColor::White,
-        rook_handle.clone(),
-        (0,7),
-    );
-
-    for i in 0..8 {
-        spawn_pawn(
-            commands,
-            white_material.clone(),
-            PieceColor::White,
-            pawn_handle.clone(),
-            (1, i)
-        );
-    }
-
-    spawn_rook(
-        commands,
-        black_material.clone(),
-        PieceColor::Black,
-        rook_handle.clone(),
-        (7, 0)
-    );
-    spawn_knight(
-        commands,
-        black_material.clone(),
-        PieceColor::Black,
-        knight_1_handle.clone(),
-        knight_2_handle.clone(),
-        (7,1)
-    );
-    spawn_bishop(
-        commands,
-        black_material.clone(),
-        PieceColor::Black,
-        bishop_handle.clone(),
-        (7,2)
-    );
-    spawn_queen(
-        commands,
-        black_material.clone(),
-        PieceColor::Black,
-        queen_handle.clone(),
-        (7,3),
-    );
-    spawn_king(
-        commands,
-        black_material.clone(),
-        PieceColor::Black,
-        king_handle.clone(),
-        king_cross_handle.clone(),
-        (7,4),
-    );
-    spawn_bishop(
-        commands,
-        black_material.clone(),
-        PieceColor::Black,
-        bishop_handle.clone(),
-        (7, 5),
-    );
-    spawn_knight(
-        commands,
-        black_material.clone(),
-        PieceColor::Black,
-        knight_1_handle.clone(),
-        knight_2_handle.clone(),
-        (7, 6),
-    );
-    spawn_rook(
-        commands,
-        black_material.clone(),
-        PieceColor::Black,
-        rook_handle.clone(),
-        (7,7),
-    );
-
-    for i in 0..8 {
-        spawn_pawn(
-            commands,
-            black_material.clone(),
-            PieceColor::Black,
-            pawn_handle.clone(),
-            (6, i),
-        );
+    // Mirror whatever the game starts from
+    for (position, piece) in game.position().pieces() {
+        spawn_piece(commands, &assets, piece, position);
     }
 }
 
@@ -569,66 +382,3 @@ fn move_pieces(time: Res<Time>, mut query: Query<(&mut Transform, &Piece)>) {
         }
     }
 }
-
-// available squares for movement =============================================================== //
-fn color_of_square(pos: (u8, u8), pieces: &Vec<Piece>) -> Option<PieceColor>{
-    for piece in pieces{
-        if piece.x == pos.0 && piece.y == pos.1{
-            return Some(piece.color);
-        }
-    }
-    None
-}
-
-
-fn is_path_empty(begin: (u8, u8), end: (u8, u8), pieces: &Vec<Piece>) -> bool {
-    // Same column
-    if begin.0 == end.0 {
-        for piece in pieces {
-            if piece.x == begin.0
-                && ((piece.y > begin.1 && piece.y < end.1)
-                || (piece.y > end.1 && piece.y < begin.1))
-            {
-                return false;
-            }
-        }
-    }
-    // Same row
-    if begin.1 == end.1 {
-        for piece in pieces {
-            if piece.y == begin.1
-                && ((piece.x > begin.0 && piece.x < end.0)
-                || (piece.x > end.0 && piece.x < begin.0))
-            {
-                return false;
-            }
-        }
-    }
-
-    // Diagonals
-    let x_diff = (begin.0 as i8 - end.0 as i8).abs();
-    let y_diff = (begin.1 as i8 - end.1 as i8).abs();
-    if x_diff == y_diff {
-        for i in 1..x_diff {
-            let pos = if begin.0 < end.0 && begin.1 < end.1 {
-                // left bottom - right top
-                (begin.0 + i as u8, begin.1 + i as u8)
-            } else if begin.0 < end.0 && begin.1 > end.1 {
-                // left top - right bottom
-                (begin.0 + i as u8, begin.1 - i as u8)
-            } else if begin.0 > end.0 && begin.1 < end.1 {
-                // right bottom - left top
-                (begin.0 - i as u8, begin.1 + i as u8)
-            } else {
-                // begin.0 > end.0 && begin.1 > end.1
-                // right top - left bottom
-                (begin.0 - i as u8, begin.1 - i as u8)
-            };
-
-            if color_of_square(pos, pieces).is_some() {
-                return false;
-            }
-        }
-    }
-    true
-}
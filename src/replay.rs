@@ -0,0 +1,98 @@
+use crate::board::ResyncPieces;
+use crate::game::Game;
+use crate::pgn::PgnGame;
+use bevy::prelude::*;
+
+/// Games loaded from a PGN file and how far into them the board is. Inactive when no file
+/// was given.
+#[derive(Default)]
+pub struct Replay {
+    games: Vec<PgnGame>,
+    game_index: usize,
+    ply: usize,
+}
+
+impl Replay {
+    pub fn new(games: Vec<PgnGame>) -> Self {
+        Self {
+            games,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.games.is_empty()
+    }
+
+    pub fn current(&self) -> Option<&PgnGame> {
+        self.games.get(self.game_index)
+    }
+
+    /// `(game, number of games)`, counting from 1
+    pub fn game_number(&self) -> (usize, usize) {
+        (self.game_index + 1, self.games.len())
+    }
+
+    /// `(half moves shown, half moves in the game)`
+    pub fn ply(&self) -> (usize, usize) {
+        (self.ply, self.current().map_or(0, |game| game.moves.len()))
+    }
+
+    /// The game as the board should show it
+    pub fn game(&self) -> Option<Game> {
+        self.current().map(|game| game.game_at(self.ply))
+    }
+}
+
+/// Arrow keys step back and forth, Home and End jump to either end, Page Up and Page Down
+/// switch between the games of the file
+fn replay_controls(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut replay: ResMut<Replay>,
+    mut game: ResMut<Game>,
+    mut resync_events: ResMut<Events<ResyncPieces>>,
+) {
+    if !replay.is_active() {
+        return;
+    }
+
+    let (mut game_index, mut ply) = (replay.game_index, replay.ply);
+    let (_, plies) = replay.ply();
+    if keyboard_input.just_pressed(KeyCode::Right) {
+        ply = (ply + 1).min(plies);
+    }
+    if keyboard_input.just_pressed(KeyCode::Left) {
+        ply = ply.saturating_sub(1);
+    }
+    if keyboard_input.just_pressed(KeyCode::Home) {
+        ply = 0;
+    }
+    if keyboard_input.just_pressed(KeyCode::End) {
+        ply = plies;
+    }
+    if keyboard_input.just_pressed(KeyCode::PageDown) && game_index + 1 < replay.games.len() {
+        game_index += 1;
+        ply = 0;
+    }
+    if keyboard_input.just_pressed(KeyCode::PageUp) && game_index > 0 {
+        game_index -= 1;
+        ply = 0;
+    }
+
+    if (game_index, ply) != (replay.game_index, replay.ply) {
+        replay.game_index = game_index;
+        replay.ply = ply;
+        if let Some(replayed) = replay.game() {
+            *game = replayed;
+            resync_events.send(ResyncPieces);
+        }
+    }
+}
+
+pub struct ReplayPlugin;
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Replay>()
+            .add_system(replay_controls.system());
+    }
+}
@@ -0,0 +1,201 @@
+//! The engine's transposition table: what earlier searches found out about positions, found
+//! again by Zobrist key when the same position comes up through another move order or in the
+//! next iteration.
+//!
+//! The table has a fixed size in megabytes and one entry per slot. A new entry replaces the
+//! one in its slot, unless that one is about another position, from the current search and
+//! searched deeper.
+
+use crate::engine::is_mate_score;
+use crate::game::Move;
+use crate::zobrist::ZobristKey;
+use std::mem::size_of;
+
+pub const DEFAULT_HASH_MB: usize = 16;
+pub const MIN_HASH_MB: usize = 1;
+pub const MAX_HASH_MB: usize = 1024;
+
+/// How a stored score relates to the true score of the position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    /// The true score is at least this, the search failed high
+    Lower,
+    /// The true score is at most this, the search failed low
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    pub key: ZobristKey,
+    pub best_move: Option<Move>,
+    /// Relative to the position stored, see `score_to_table`
+    pub score: i16,
+    pub depth: u8,
+    pub bound: Bound,
+    /// Search the entry was stored in
+    age: u8,
+}
+
+pub struct TranspositionTable {
+    entries: Vec<Option<Entry>>,
+    megabytes: usize,
+    /// Goes up with every search, so entries of older ones are replaced first
+    age: u8,
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        Self::new(DEFAULT_HASH_MB)
+    }
+}
+
+impl TranspositionTable {
+    /// A table taking about `megabytes` of memory, clamped to the allowed sizes
+    pub fn new(megabytes: usize) -> Self {
+        let megabytes = megabytes.clamp(MIN_HASH_MB, MAX_HASH_MB);
+        let len = megabytes * 1024 * 1024 / size_of::<Option<Entry>>();
+        Self {
+            entries: vec![None; len],
+            megabytes,
+            age: 0,
+        }
+    }
+
+    pub fn megabytes(&self) -> usize {
+        self.megabytes
+    }
+
+    /// Reallocates the table, forgetting everything in it
+    pub fn resize(&mut self, megabytes: usize) {
+        *self = Self::new(megabytes);
+    }
+
+    pub fn clear(&mut self) {
+        for entry in self.entries.iter_mut() {
+            *entry = None;
+        }
+        self.age = 0;
+    }
+
+    /// To call before every search, ages what the earlier ones stored
+    pub fn new_search(&mut self) {
+        self.age = self.age.wrapping_add(1);
+    }
+
+    /// Per mille of the table used by the current search, estimated from its start
+    pub fn hashfull(&self) -> u32 {
+        let sample = &self.entries[..self.entries.len().min(1000)];
+        let used = sample
+            .iter()
+            .filter(|entry| matches!(entry, Some(entry) if entry.age == self.age))
+            .count();
+        (used * 1000 / sample.len()) as u32
+    }
+
+    fn index(&self, key: ZobristKey) -> usize {
+        (key % self.entries.len() as u64) as usize
+    }
+
+    pub fn probe(&self, key: ZobristKey) -> Option<Entry> {
+        self.entries[self.index(key)].filter(|entry| entry.key == key)
+    }
+
+    /// Stores what a search `depth` half moves deep found about the position with `key`, `ply`
+    /// half moves from the root
+    pub fn store(
+        &mut self,
+        key: ZobristKey,
+        depth: u32,
+        bound: Bound,
+        score: i32,
+        best_move: Option<Move>,
+        ply: usize,
+    ) {
+        let age = self.age;
+        let index = self.index(key);
+        let slot = &mut self.entries[index];
+        let best_move = match slot {
+            // Keep the old move rather than none for the same position
+            Some(old) if old.key == key => best_move.or(old.best_move),
+            // Deeper results of this search are worth more than a new shallow one
+            Some(old) if old.age == age && u32::from(old.depth) > depth => return,
+            _ => best_move,
+        };
+        *slot = Some(Entry {
+            key,
+            best_move,
+            score: score_to_table(score, ply) as i16,
+            depth: depth.min(u32::from(u8::MAX)) as u8,
+            bound,
+            age,
+        });
+    }
+}
+
+/// Mate scores count the half moves from the root, the table stores them counted from the
+/// position itself so they stay right when it is found at another ply
+pub fn score_to_table(score: i32, ply: usize) -> i32 {
+    if !is_mate_score(score) {
+        score
+    } else if score > 0 {
+        score + ply as i32
+    } else {
+        score - ply as i32
+    }
+}
+
+/// Undoes `score_to_table` for a position `ply` half moves from the root
+pub fn score_from_table(score: i32, ply: usize) -> i32 {
+    if !is_mate_score(score) {
+        score
+    } else if score > 0 {
+        score - ply as i32
+    } else {
+        score + ply as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::MATE;
+
+    #[test]
+    fn mate_scores_are_stored_from_the_position() {
+        // Mate in 3 plies from a node 4 plies deep is mate in 7 from the root
+        let score = MATE - 7;
+        assert_eq!(score_to_table(score, 4), MATE - 3);
+        assert_eq!(score_from_table(MATE - 3, 4), score);
+        assert_eq!(
+            score_from_table(score_to_table(-score, 4), 10),
+            -(MATE - 13)
+        );
+        assert_eq!(score_to_table(150, 4), 150);
+    }
+
+    #[test]
+    fn deeper_entries_of_the_same_search_stay() {
+        let mut table = TranspositionTable::new(MIN_HASH_MB);
+        let len = table.entries.len() as u64;
+        let (key, other) = (12345, 12345 + len);
+        let mv = Move::new((1, 4), (3, 4));
+
+        table.new_search();
+        table.store(key, 6, Bound::Exact, 20, Some(mv), 0);
+        table.store(other, 2, Bound::Lower, 50, None, 0);
+        assert_eq!(table.probe(key).map(|entry| entry.depth), Some(6));
+        assert_eq!(table.probe(other), None);
+
+        // Shallower results for the same position replace it, keeping its move
+        table.store(key, 3, Bound::Upper, -10, None, 0);
+        let entry = table.probe(key).unwrap();
+        assert_eq!((entry.depth, entry.best_move), (3, Some(mv)));
+
+        // The next search replaces what the previous one stored
+        table.new_search();
+        table.store(other, 1, Bound::Lower, 50, None, 0);
+        assert_eq!(table.probe(key), None);
+        assert_eq!(table.probe(other).map(|entry| entry.score), Some(50));
+    }
+}
@@ -0,0 +1,376 @@
+//! The Universal Chess Interface, so chess GUIs and testing tools can use the engine. They
+//! send commands one per line, and the engine answers the same way. The `rustic_chess_uci`
+//! binary runs it on stdin and stdout.
+
+use crate::engine::{is_mate_score, SearchInfo, SearchLimits, Searcher, MATE};
+use crate::fen::parse_fen;
+use crate::game::{PieceColor, Position};
+use crate::notation::{parse_uci, to_uci};
+use crate::transposition::{TranspositionTable, DEFAULT_HASH_MB, MAX_HASH_MB, MIN_HASH_MB};
+use crate::zobrist::ZobristKey;
+use std::io::{BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const NAME: &str = "Rustic Chess";
+const AUTHOR: &str = "gandhi56";
+
+/// Moves the remaining time is shared between when the GUI doesn't say
+const DEFAULT_MOVES_TO_GO: u64 = 30;
+
+/// Time kept back on the clock for the answer to reach the GUI
+const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+
+/// Answers the commands read from `input` on `output`, until "quit" or the end of the input.
+/// At the end of the input, a search going on gets to finish unless it is infinite.
+pub fn run<R: BufRead, W: Write + Send + 'static>(input: R, output: W) {
+    let mut engine = Engine::new(output);
+    for line in input.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if !engine.handle(&line) {
+            return;
+        }
+    }
+    engine.wait_for_search(false);
+}
+
+fn send<W: Write>(output: &Mutex<W>, text: &str) {
+    let mut output = output.lock().unwrap();
+    // Nothing to do if the GUI went away
+    let _ = writeln!(output, "{}", text);
+    let _ = output.flush();
+}
+
+/// A search running on its own thread, so commands such as "stop" are read meanwhile. The
+/// thread sends the "info" and "bestmove" lines itself.
+struct RunningSearch {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+    infinite: bool,
+}
+
+struct Engine<W> {
+    output: Arc<Mutex<W>>,
+    table: Arc<Mutex<TranspositionTable>>,
+    position: Position,
+    /// Keys of the positions before `position`, oldest first
+    history: Vec<ZobristKey>,
+    search: Option<RunningSearch>,
+}
+
+impl<W: Write + Send + 'static> Engine<W> {
+    fn new(output: W) -> Self {
+        Self {
+            output: Arc::new(Mutex::new(output)),
+            table: Arc::default(),
+            position: Position::initial(),
+            history: Vec::new(),
+            search: None,
+        }
+    }
+
+    fn send(&self, text: &str) {
+        send(&self.output, text);
+    }
+
+    /// Carries out one command. Returns false for "quit".
+    fn handle(&mut self, line: &str) -> bool {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match tokens.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return true,
+        };
+
+        match command {
+            "uci" => {
+                self.send(&format!("id name {}", NAME));
+                self.send(&format!("id author {}", AUTHOR));
+                self.send(&format!(
+                    "option name Hash type spin default {} min {} max {}",
+                    DEFAULT_HASH_MB, MIN_HASH_MB, MAX_HASH_MB
+                ));
+                self.send("uciok");
+            }
+            "isready" => self.send("readyok"),
+            "setoption" => self.set_option(args),
+            "ucinewgame" => {
+                self.wait_for_search(true);
+                self.table.lock().unwrap().clear();
+                self.position = Position::initial();
+                self.history.clear();
+            }
+            "position" => {
+                if let Err(error) = self.set_position(args) {
+                    self.send(&format!("info string {}", error));
+                }
+            }
+            "go" => self.go(args),
+            "stop" => self.wait_for_search(true),
+            "quit" => {
+                self.wait_for_search(true);
+                return false;
+            }
+            _ => self.send(&format!("info string unknown command {}", command)),
+        }
+        true
+    }
+
+    /// "setoption name <name> value <value>"
+    fn set_option(&mut self, args: &[&str]) {
+        let value_index = args
+            .iter()
+            .position(|arg| *arg == "value")
+            .unwrap_or(args.len());
+        let name = args.get(1..value_index).unwrap_or_default().join(" ");
+        let value = args.get(value_index + 1..).unwrap_or_default().join(" ");
+
+        if name.eq_ignore_ascii_case("Hash") {
+            match value.parse() {
+                Ok(megabytes) => {
+                    self.wait_for_search(true);
+                    self.table.lock().unwrap().resize(megabytes);
+                }
+                Err(_) => self.send(&format!("info string bad hash size {}", value)),
+            }
+        } else {
+            self.send(&format!("info string unknown option {}", name));
+        }
+    }
+
+    /// "position startpos|fen <fen> [moves <move>...]"
+    fn set_position(&mut self, args: &[&str]) -> Result<(), String> {
+        let moves_index = args
+            .iter()
+            .position(|arg| *arg == "moves")
+            .unwrap_or(args.len());
+        let mut position = match args.first() {
+            Some(&"startpos") => Position::initial(),
+            Some(&"fen") => parse_fen(&args[1..moves_index].join(" "))
+                .map_err(|error| format!("invalid FEN: {}", error))?,
+            _ => return Err("position needs startpos or fen".to_string()),
+        };
+
+        let mut history = Vec::new();
+        for text in args.iter().skip(moves_index + 1) {
+            let mv = parse_uci(&position, text).ok_or_else(|| format!("illegal move {}", text))?;
+            history.push(position.key());
+            position.apply_move(mv);
+        }
+        self.position = position;
+        self.history = history;
+        Ok(())
+    }
+
+    /// "go" with any of "depth", "nodes", "movetime", "wtime", "btime", "winc", "binc",
+    /// "movestogo" and "infinite"
+    fn go(&mut self, args: &[&str]) {
+        self.wait_for_search(true);
+
+        let number = |name: &str| {
+            let index = args.iter().position(|arg| *arg == name)?;
+            // Some GUIs send negative times once a clock ran out
+            let value: i64 = args.get(index + 1)?.parse().ok()?;
+            Some(value.max(0) as u64)
+        };
+        let millis = |name: &str| number(name).map(Duration::from_millis);
+
+        let mut limits = SearchLimits {
+            depth: number("depth").map(|depth| depth as u32),
+            movetime: millis("movetime"),
+            nodes: number("nodes"),
+        };
+        let (time, increment) = match self.position.turn() {
+            PieceColor::White => (millis("wtime"), millis("winc")),
+            PieceColor::Black => (millis("btime"), millis("binc")),
+        };
+        if let Some(time) = time {
+            let budget = time_for_move(time, increment.unwrap_or_default(), number("movestogo"));
+            limits.movetime = Some(
+                limits
+                    .movetime
+                    .map_or(budget, |movetime| movetime.min(budget)),
+            );
+        }
+        let infinite = args.contains(&"infinite");
+
+        let mut searcher = Searcher::with_table(self.table.clone());
+        let stop = searcher.stop_flag();
+        let thread_stop = stop.clone();
+        let output = self.output.clone();
+        let table = self.table.clone();
+        let position = self.position.clone();
+        let history = self.history.clone();
+        let thread = thread::spawn(move || {
+            let info = searcher.search(&position, &history, limits, |info| {
+                let hashfull = table.lock().unwrap().hashfull();
+                send(&output, &info_line(info, hashfull));
+            });
+
+            // An infinite search only answers once told to stop
+            while infinite && !thread_stop.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(5));
+            }
+            // Stopped before the first iteration finished, any legal move beats none
+            let best_move = info
+                .best_move()
+                .or_else(|| position.legal_moves().first().copied());
+            let best_move = best_move.map_or_else(|| "0000".to_string(), to_uci);
+            send(&output, &format!("bestmove {}", best_move));
+        });
+
+        self.search = Some(RunningSearch {
+            stop,
+            thread,
+            infinite,
+        });
+    }
+
+    /// Waits until the search going on, if any, has sent its best move. With `stop`, or if
+    /// the search is infinite, tells it to stop first.
+    fn wait_for_search(&mut self, stop: bool) {
+        if let Some(search) = self.search.take() {
+            if stop || search.infinite {
+                search.stop.store(true, Ordering::Relaxed);
+            }
+            let _ = search.thread.join();
+        }
+    }
+}
+
+/// Time to spend on the move with `time` left: an even share of it for each move to go,
+/// plus most of the increment, but never all that is left
+fn time_for_move(time: Duration, increment: Duration, moves_to_go: Option<u64>) -> Duration {
+    let moves = moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1) as u32;
+    let budget = time / moves + increment * 3 / 4;
+    budget.min(time.checked_sub(MOVE_OVERHEAD).unwrap_or_default())
+}
+
+/// "info" line reporting a finished iteration
+fn info_line(info: &SearchInfo, hashfull: u32) -> String {
+    let score = if is_mate_score(info.score) {
+        // The engine counts mates in half moves, UCI in moves
+        let moves = if info.score > 0 {
+            (MATE - info.score + 1) / 2
+        } else {
+            -(MATE + info.score) / 2
+        };
+        format!("mate {}", moves)
+    } else {
+        format!("cp {}", info.score)
+    };
+    let millis = info.time.as_millis();
+    let nps = u128::from(info.nodes) * 1000 / millis.max(1);
+    let pv: Vec<String> = info.pv.iter().map(|mv| to_uci(*mv)).collect();
+    format!(
+        "info depth {} score {} nodes {} nps {} hashfull {} time {} pv {}",
+        info.depth,
+        score,
+        info.nodes,
+        nps,
+        hashfull,
+        millis,
+        pv.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{self, Cursor};
+
+    /// Output the test can still read after `run` took it
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run_script(script: &str) -> Vec<String> {
+        let output = SharedOutput::default();
+        run(Cursor::new(script.to_string()), output.clone());
+        let bytes = output.0.lock().unwrap().clone();
+        String::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn handshake() {
+        let lines = run_script("uci\nisready\nquit\n");
+        assert_eq!(lines[0], "id name Rustic Chess");
+        assert!(lines.contains(&"option name Hash type spin default 16 min 1 max 1024".into()));
+        assert_eq!(lines[lines.len() - 2..], ["uciok", "readyok"]);
+    }
+
+    #[test]
+    fn finds_mate() {
+        let lines = run_script("position fen 6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1\ngo depth 3\n");
+        assert!(lines.iter().any(|line| line.contains("score mate 1 ")));
+        assert_eq!(lines.last().unwrap(), "bestmove a1a8");
+    }
+
+    #[test]
+    fn plays_from_the_moves_given() {
+        let lines = run_script(
+            "position startpos moves e2e4 e7e5 g1f3\ngo nodes 5000\nposition startpos moves e2e5\n",
+        );
+        let best_move = lines
+            .iter()
+            .find_map(|line| line.strip_prefix("bestmove "))
+            .unwrap();
+
+        let mut position = Position::initial();
+        for uci in ["e2e4", "e7e5", "g1f3"].iter() {
+            position.apply_move(parse_uci(&position, uci).unwrap());
+        }
+        assert!(parse_uci(&position, best_move).is_some());
+        // The search may still be running when the engine reads the next line
+        assert!(lines.contains(&"info string illegal move e2e5".into()));
+    }
+
+    #[test]
+    fn hash_option() {
+        let mut engine = Engine::new(Vec::new());
+        engine.handle("setoption name Hash value 4");
+        assert_eq!(engine.table.lock().unwrap().megabytes(), 4);
+        engine.handle("setoption name Hash value 100000");
+        assert_eq!(engine.table.lock().unwrap().megabytes(), MAX_HASH_MB);
+    }
+
+    #[test]
+    fn infinite_search_waits_for_stop() {
+        let lines = run_script("position startpos\ngo infinite\nstop\n");
+        assert!(lines.last().unwrap().starts_with("bestmove "));
+    }
+
+    #[test]
+    fn time_management() {
+        let second = Duration::from_secs(1);
+        assert_eq!(
+            time_for_move(30 * second, second, None),
+            1750 * second / 1000
+        );
+        assert_eq!(
+            time_for_move(10 * second, Duration::default(), Some(5)),
+            2 * second
+        );
+        assert_eq!(
+            time_for_move(second / 10, Duration::default(), Some(1)),
+            second / 20
+        );
+    }
+}
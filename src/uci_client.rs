@@ -0,0 +1,527 @@
+//! The other side of the Universal Chess Interface: runs a UCI engine from another program as
+//! a child process, to play against it or analyse with it.
+//!
+//! The engine's output is read on its own thread, and `UciClient::try_message` hands over what
+//! it said without blocking, so the board keeps drawing while the engine thinks.
+
+use crate::engine::SearchLimits;
+use crate::fen::to_fen;
+use crate::game::{Move, Position};
+use crate::notation::to_uci;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long the engine gets to answer "uci" and "isready" when starting
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the engine gets to exit by itself after "quit"
+const QUIT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Which engine to run, and the UCI options to set on it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EngineConfig {
+    pub path: PathBuf,
+    /// Names and values, sent with "setoption" in this order
+    pub options: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// A line that isn't "name = value", numbered from 1
+    BadLine(usize),
+    /// No "path" to the engine
+    NoPath,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::BadLine(line) => write!(f, "line {} is not \"name = value\"", line),
+            ConfigError::NoPath => write!(f, "no path to the engine"),
+        }
+    }
+}
+
+impl EngineConfig {
+    /// Reads a config file with one "name = value" per line: "path" is the engine to run, and
+    /// every other name a UCI option to set on it. Blank lines and lines starting with '#' are
+    /// skipped.
+    ///
+    /// ```text
+    /// path = /usr/games/stockfish
+    /// Threads = 4
+    /// Skill Level = 10
+    /// ```
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = match line.find('=') {
+                Some(equals) if equals > 0 => (line[..equals].trim(), line[equals + 1..].trim()),
+                _ => return Err(ConfigError::BadLine(index + 1)),
+            };
+            if name == "path" {
+                config.path = value.into();
+            } else {
+                config.options.push((name.to_string(), value.to_string()));
+            }
+        }
+
+        if config.path.as_os_str().is_empty() {
+            return Err(ConfigError::NoPath);
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug)]
+pub enum UciError {
+    Io(io::Error),
+    /// The engine didn't answer in time while starting
+    Timeout(&'static str),
+    /// The engine closed its output, most likely because it exited
+    Exited,
+}
+
+impl fmt::Display for UciError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UciError::Io(error) => write!(f, "{}", error),
+            UciError::Timeout(expected) => write!(f, "engine didn't send \"{}\" in time", expected),
+            UciError::Exited => write!(f, "engine exited"),
+        }
+    }
+}
+
+impl From<io::Error> for UciError {
+    fn from(error: io::Error) -> Self {
+        UciError::Io(error)
+    }
+}
+
+/// Score as reported by the engine, from the side to move's point of view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Score {
+    Centipawns(i32),
+    /// Mate in that many moves, negative when the side to move gets mated
+    Mate(i32),
+}
+
+/// The parts of an "info" line the board shows
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EngineInfo {
+    pub depth: Option<u32>,
+    pub score: Option<Score>,
+    pub nodes: Option<u64>,
+    /// Principal variation in UCI notation
+    pub pv: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineMessage {
+    Info(EngineInfo),
+    /// `None` if the engine had no move to play
+    BestMove(Option<String>),
+}
+
+/// Reads the depth, score, nodes and principal variation of an "info" line, `None` for
+/// other lines and for "info string"
+pub fn parse_info(line: &str) -> Option<EngineInfo> {
+    let mut tokens = line.split_whitespace();
+    if tokens.next() != Some("info") {
+        return None;
+    }
+
+    let mut info = EngineInfo::default();
+    while let Some(token) = tokens.next() {
+        match token {
+            "depth" => info.depth = tokens.next().and_then(|depth| depth.parse().ok()),
+            "nodes" => info.nodes = tokens.next().and_then(|nodes| nodes.parse().ok()),
+            "score" => {
+                let kind = tokens.next();
+                let value = tokens.next().and_then(|value| value.parse().ok());
+                info.score = match (kind, value) {
+                    (Some("cp"), Some(value)) => Some(Score::Centipawns(value)),
+                    (Some("mate"), Some(value)) => Some(Score::Mate(value)),
+                    _ => None,
+                };
+            }
+            // Both run to the end of the line
+            "pv" => info.pv = tokens.by_ref().map(str::to_string).collect(),
+            "string" => return None,
+            _ => {}
+        }
+    }
+    Some(info)
+}
+
+/// Reads a "bestmove" line
+fn parse_best_move(line: &str) -> Option<Option<String>> {
+    let mut tokens = line.split_whitespace();
+    if tokens.next() != Some("bestmove") {
+        return None;
+    }
+    Some(
+        tokens
+            .next()
+            .filter(|mv| *mv != "0000" && *mv != "(none)")
+            .map(str::to_string),
+    )
+}
+
+/// "position" command for the position after playing `moves` from `start`
+pub fn position_command(start: &Position, moves: &[Move]) -> String {
+    let mut command = format!("position fen {}", to_fen(start));
+    if !moves.is_empty() {
+        command.push_str(" moves");
+        for mv in moves {
+            command.push(' ');
+            command.push_str(&to_uci(*mv));
+        }
+    }
+    command
+}
+
+/// "go" command searching within `limits`, or until stopped if there are none
+pub fn go_command(limits: SearchLimits) -> String {
+    let mut command = "go".to_string();
+    if let Some(depth) = limits.depth {
+        command.push_str(&format!(" depth {}", depth));
+    }
+    if let Some(nodes) = limits.nodes {
+        command.push_str(&format!(" nodes {}", nodes));
+    }
+    if let Some(movetime) = limits.movetime {
+        command.push_str(&format!(" movetime {}", movetime.as_millis()));
+    }
+    if limits == SearchLimits::default() {
+        command.push_str(" infinite");
+    }
+    command
+}
+
+/// A running UCI engine
+pub struct UciClient {
+    child: Child,
+    stdin: ChildStdin,
+    /// Lines the engine printed, read by a thread of their own
+    lines: Mutex<Receiver<String>>,
+    name: String,
+    searching: bool,
+    /// Searches that were stopped or replaced, whose "bestmove" is still to come and to skip
+    stale: u32,
+}
+
+impl UciClient {
+    /// Starts the engine, waits for it to be ready and sets the options of `config`
+    pub fn start(config: &EngineConfig) -> Result<Self, UciError> {
+        let mut child = Command::new(&config.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or(UciError::Exited)?;
+        let stdout = child.stdout.take().ok_or(UciError::Exited)?;
+
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            // Stops when the engine exits, or when nobody listens anymore
+            for line in BufReader::new(stdout).lines() {
+                let sent = line.map(|line| sender.send(line).is_ok());
+                if !matches!(sent, Ok(true)) {
+                    break;
+                }
+            }
+        });
+
+        let mut client = Self {
+            child,
+            stdin,
+            lines: Mutex::new(receiver),
+            name: config.path.display().to_string(),
+            searching: false,
+            stale: 0,
+        };
+        client.send("uci")?;
+        for line in client.lines_until("uciok")? {
+            if let Some(name) = line.strip_prefix("id name ") {
+                client.name = name.trim().to_string();
+            }
+        }
+        for (name, value) in config.options.iter() {
+            client.send(&format!("setoption name {} value {}", name, value))?;
+        }
+        client.send("isready")?;
+        client.lines_until("readyok")?;
+        Ok(client)
+    }
+
+    /// Name the engine gave, or its path if it didn't
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.stdin, "{}", command)?;
+        self.stdin.flush()
+    }
+
+    /// Lines the engine sends up to `expected`, which must come within `HANDSHAKE_TIMEOUT`
+    fn lines_until(&self, expected: &'static str) -> Result<Vec<String>, UciError> {
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        let lines = self.lines.lock().unwrap();
+        let mut before = Vec::new();
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match lines.recv_timeout(timeout) {
+                Ok(line) if line.trim() == expected => return Ok(before),
+                Ok(line) => before.push(line),
+                Err(RecvTimeoutError::Timeout) => return Err(UciError::Timeout(expected)),
+                Err(RecvTimeoutError::Disconnected) => return Err(UciError::Exited),
+            }
+        }
+    }
+
+    /// Tells the engine the next positions are from another game
+    pub fn new_game(&mut self) -> Result<(), UciError> {
+        self.stop()?;
+        self.send("ucinewgame")?;
+        Ok(())
+    }
+
+    /// Searches the position after `moves` from `start`, replacing the search going on
+    pub fn go(
+        &mut self,
+        start: &Position,
+        moves: &[Move],
+        limits: SearchLimits,
+    ) -> Result<(), UciError> {
+        self.stop()?;
+        self.send(&position_command(start, moves))?;
+        self.send(&go_command(limits))?;
+        self.searching = true;
+        Ok(())
+    }
+
+    /// Stops the search going on, if any. Its best move is skipped.
+    pub fn stop(&mut self) -> Result<(), UciError> {
+        if self.searching {
+            self.send("stop")?;
+            self.searching = false;
+            self.stale += 1;
+        }
+        Ok(())
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.searching
+    }
+
+    /// The next thing the engine said about the latest search, if it said anything yet.
+    /// Lines about searches that were stopped or replaced are skipped.
+    pub fn try_message(&mut self) -> Result<Option<EngineMessage>, UciError> {
+        loop {
+            let line = match self.lines.lock().unwrap().try_recv() {
+                Ok(line) => line,
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Disconnected) => return Err(UciError::Exited),
+            };
+
+            if let Some(best_move) = parse_best_move(&line) {
+                if self.stale > 0 {
+                    self.stale -= 1;
+                    continue;
+                }
+                self.searching = false;
+                return Ok(Some(EngineMessage::BestMove(best_move)));
+            }
+            if let Some(info) = parse_info(&line) {
+                if self.stale == 0 {
+                    return Ok(Some(EngineMessage::Info(info)));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for UciClient {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let deadline = Instant::now() + QUIT_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_file() {
+        let text = "# Stockfish, a bit weaker\n\
+                    path = /usr/games/stockfish\n\
+                    \n\
+                    Threads = 4\n\
+                    Skill Level=10\n";
+        assert_eq!(
+            EngineConfig::parse(text),
+            Ok(EngineConfig {
+                path: PathBuf::from("/usr/games/stockfish"),
+                options: vec![
+                    (String::from("Threads"), String::from("4")),
+                    (String::from("Skill Level"), String::from("10")),
+                ],
+            })
+        );
+
+        assert_eq!(
+            EngineConfig::parse("path = stockfish\nThreads 4"),
+            Err(ConfigError::BadLine(2))
+        );
+        assert_eq!(
+            EngineConfig::parse("= 4\npath = stockfish"),
+            Err(ConfigError::BadLine(1))
+        );
+        assert_eq!(EngineConfig::parse("Threads = 4"), Err(ConfigError::NoPath));
+    }
+
+    #[test]
+    fn info_lines() {
+        let info = parse_info(
+            "info depth 12 seldepth 18 score cp -35 upperbound nodes 91234 nps 1000 \
+             pv e7e5 g1f3 b8c6",
+        )
+        .unwrap();
+        assert_eq!(
+            info,
+            EngineInfo {
+                depth: Some(12),
+                score: Some(Score::Centipawns(-35)),
+                nodes: Some(91234),
+                pv: vec!["e7e5".into(), "g1f3".into(), "b8c6".into()],
+            }
+        );
+        assert_eq!(
+            parse_info("info depth 3 score mate -2").unwrap().score,
+            Some(Score::Mate(-2))
+        );
+        assert_eq!(parse_info("info string NNUE enabled"), None);
+        assert_eq!(parse_info("bestmove e2e4"), None);
+    }
+
+    #[test]
+    fn commands() {
+        let start = Position::initial();
+        let e4 = Move::new((1, 4), (3, 4));
+        assert_eq!(
+            position_command(&start, &[e4]),
+            "position fen rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 moves e2e4"
+        );
+        let limits = SearchLimits {
+            depth: Some(8),
+            movetime: Some(Duration::from_millis(1500)),
+            ..SearchLimits::default()
+        };
+        assert_eq!(go_command(limits), "go depth 8 movetime 1500");
+        assert_eq!(go_command(SearchLimits::default()), "go infinite");
+    }
+
+    /// A shell script standing in for an engine: it logs what it is told, and answers every
+    /// "go" at once with the same line and move
+    #[cfg(unix)]
+    const STUB_ENGINE: &str = r#"#!/bin/sh
+while read -r line; do
+    echo "$line" >> "$0.log"
+    case "$line" in
+        uci) echo "id name Stub Engine"; echo "uciok" ;;
+        isready) echo "readyok" ;;
+        go*) echo "info depth 1 score cp 13 nodes 20 pv e2e4 e7e5"; echo "bestmove e2e4" ;;
+        quit) exit 0 ;;
+    esac
+done
+"#;
+
+    #[cfg(unix)]
+    fn stub_engine(name: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("{}-{}.sh", name, std::process::id()));
+        std::fs::write(&path, STUB_ENGINE).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[cfg(unix)]
+    fn wait_for_message(client: &mut UciClient) -> EngineMessage {
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Some(message) = client.try_message().unwrap() {
+                return message;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        panic!("the engine didn't answer");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn plays_against_stub_engine() {
+        let path = stub_engine("rustic-chess-stub");
+        let config = EngineConfig {
+            path: path.clone(),
+            options: vec![("Hash".into(), "32".into())],
+        };
+        let mut client = UciClient::start(&config).unwrap();
+        assert_eq!(client.name(), "Stub Engine");
+
+        // The first search is replaced before its answer is read, so only the second counts
+        let start = Position::initial();
+        client.go(&start, &[], SearchLimits::default()).unwrap();
+        let e4 = Move::new((1, 4), (3, 4));
+        let limits = SearchLimits {
+            depth: Some(1),
+            ..SearchLimits::default()
+        };
+        client.go(&start, &[e4], limits).unwrap();
+        assert_eq!(
+            wait_for_message(&mut client),
+            EngineMessage::Info(EngineInfo {
+                depth: Some(1),
+                score: Some(Score::Centipawns(13)),
+                nodes: Some(20),
+                pv: vec!["e2e4".into(), "e7e5".into()],
+            })
+        );
+        assert_eq!(
+            wait_for_message(&mut client),
+            EngineMessage::BestMove(Some("e2e4".into()))
+        );
+        assert!(!client.is_searching());
+        drop(client);
+
+        let log_path = PathBuf::from(format!("{}.log", path.display()));
+        let log = std::fs::read_to_string(&log_path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&log_path);
+        let log: Vec<&str> = log.lines().collect();
+        assert_eq!(log[..3], ["uci", "setoption name Hash value 32", "isready"]);
+        assert!(log.contains(&"stop"));
+        assert!(log.contains(&"go depth 1"));
+        assert_eq!(log.last(), Some(&"quit"));
+    }
+}
@@ -1,9 +1,38 @@
-use crate::{board::*, pieces::*};
-use bevy::prelude::*;
+use crate::{
+    board::*,
+    clock::{format_time, GameClock},
+    external::ExternalAnalysis,
+    game::{Game, GameOver, GameOverReason, Move, PROMOTION_TYPES},
+    notation::{parse_uci, to_san},
+    opponent::{EngineOpponent, EngineThinking},
+    pieces::*,
+    replay::Replay,
+    transposition::{MAX_HASH_MB, MIN_HASH_MB},
+    uci_client::Score,
+};
+use bevy::{input::mouse::MouseWheel, prelude::*};
 
 // Component to mark the Text entity
 struct NextMoveText;
 
+// Component to mark the clock times
+struct ClockText;
+
+// Component to mark the text telling the engine is thinking
+struct ThinkingText;
+
+// Component to mark the text telling a draw can be claimed
+struct DrawClaimText;
+
+// Component to mark the replay status text
+struct ReplayText;
+
+// Component to mark what the external engine thinks of the position
+struct AnalysisText;
+
+/// Button taking back or replaying a move
+struct UndoRedoButton(UndoRedo);
+
 /// Initialize UiCamera and text
 fn init_next_move_text(
     commands: &mut Commands,
@@ -12,6 +41,7 @@ fn init_next_move_text(
 ) {
     let font = asset_server.load("fonts/FiraSans-Bold.ttf");
     let material = color_materials.add(Color::NONE.into());
+    let button_material = color_materials.add(Color::rgb(0.15, 0.15, 0.15).into());
 
     commands
         .spawn(CameraUiBundle::default())
@@ -24,6 +54,7 @@ fn init_next_move_text(
                     top: Val::Px(10.),
                     ..Default::default()
                 },
+                flex_direction: FlexDirection::ColumnReverse,
                 ..Default::default()
             },
             material,
@@ -34,7 +65,7 @@ fn init_next_move_text(
                 .spawn(TextBundle {
                     text: Text {
                         value: "Next move: White".to_string(),
-                        font,
+                        font: font.clone(),
                         style: TextStyle {
                             font_size: 40.0,
                             color: Color::rgb(0.8, 0.8, 0.8),
@@ -44,9 +75,133 @@ fn init_next_move_text(
                     ..Default::default()
                 })
                 .with(NextMoveText);
+            parent
+                .spawn(TextBundle {
+                    text: Text {
+                        value: String::new(),
+                        font: font.clone(),
+                        style: TextStyle {
+                            font_size: 40.0,
+                            color: Color::rgb(0.8, 0.8, 0.8),
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                })
+                .with(ClockText);
+            parent
+                .spawn(TextBundle {
+                    text: Text {
+                        value: String::new(),
+                        font: font.clone(),
+                        style: TextStyle {
+                            font_size: 30.0,
+                            color: Color::rgb(0.6, 0.9, 0.6),
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                })
+                .with(ThinkingText);
+            parent
+                .spawn(TextBundle {
+                    text: Text {
+                        value: String::new(),
+                        font: font.clone(),
+                        style: TextStyle {
+                            font_size: 30.0,
+                            color: Color::rgb(0.8, 0.8, 0.3),
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                })
+                .with(DrawClaimText);
+            parent
+                .spawn(TextBundle {
+                    text: Text {
+                        value: String::new(),
+                        font: font.clone(),
+                        style: TextStyle {
+                            font_size: 30.0,
+                            color: Color::rgb(0.6, 0.8, 0.9),
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                })
+                .with(ReplayText);
+            parent
+                .spawn(TextBundle {
+                    text: Text {
+                        value: String::new(),
+                        font: font.clone(),
+                        style: TextStyle {
+                            font_size: 30.0,
+                            color: Color::rgb(0.6, 0.9, 0.6),
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                })
+                .with(AnalysisText);
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        ..Default::default()
+                    },
+                    material: material.clone(),
+                    ..Default::default()
+                })
+                .with_children(|parent| {
+                    for (label, action) in
+                        [("Undo", UndoRedo::Undo), ("Redo", UndoRedo::Redo)].iter()
+                    {
+                        parent
+                            .spawn(ButtonBundle {
+                                style: Style {
+                                    size: Size::new(Val::Px(100.), Val::Px(40.)),
+                                    margin: Rect::all(Val::Px(5.)),
+                                    justify_content: JustifyContent::Center,
+                                    align_items: AlignItems::Center,
+                                    ..Default::default()
+                                },
+                                material: button_material.clone(),
+                                ..Default::default()
+                            })
+                            .with(UndoRedoButton(*action))
+                            .with_children(|parent| {
+                                parent.spawn(TextBundle {
+                                    text: Text {
+                                        value: label.to_string(),
+                                        font: font.clone(),
+                                        style: TextStyle {
+                                            font_size: 30.0,
+                                            color: Color::rgb(0.9, 0.9, 0.9),
+                                            ..Default::default()
+                                        },
+                                    },
+                                    ..Default::default()
+                                });
+                            });
+                    }
+                });
         });
 }
 
+/// Forward clicks on the undo and redo buttons to the board
+fn undo_redo_buttons(
+    mut undo_redo_events: ResMut<Events<UndoRedo>>,
+    interaction_query: Query<(&Interaction, &UndoRedoButton), Mutated<Interaction>>,
+) {
+    for (interaction, button) in interaction_query.iter() {
+        if *interaction == Interaction::Clicked {
+            undo_redo_events.send(button.0);
+        }
+    }
+}
+
 /// Update text with the correct turn
 fn next_move_text_update(
     turn: ChangedRes<PlayerTurn>,
@@ -63,18 +218,717 @@ fn next_move_text_update(
     }
 }
 
-/// Demo system to show off Query transformers
-fn log_text_changes(query: Query<&Text, Mutated<Text>>) {
-    for text in query.iter() {
-        println!("New text: {}", text.value);
+/// Show both clocks of a timed game, the one running first
+fn clock_text_update(
+    clock: ChangedRes<GameClock>,
+    turn: Res<PlayerTurn>,
+    mut query: Query<(&mut Text, &ClockText)>,
+) {
+    let clock = match clock.0.as_ref() {
+        Some(clock) => clock,
+        None => return,
+    };
+
+    let time = |color| format_time(clock.remaining(color));
+    let value = match turn.0 {
+        PieceColor::White => format!(
+            "White {}  |  Black {}",
+            time(PieceColor::White),
+            time(PieceColor::Black)
+        ),
+        PieceColor::Black => format!(
+            "Black {}  |  White {}",
+            time(PieceColor::Black),
+            time(PieceColor::White)
+        ),
+    };
+    for (mut text, _tag) in query.iter_mut() {
+        // Only touch the text when the shown time changes
+        if text.value != value {
+            text.value = value.clone();
+        }
+    }
+}
+
+/// Tell the player while the engine is working on its move
+fn thinking_text_update(
+    thinking: ChangedRes<EngineThinking>,
+    mut query: Query<(&mut Text, &ThinkingText)>,
+) {
+    for (mut text, _tag) in query.iter_mut() {
+        text.value = if thinking.is_thinking() {
+            "Engine is thinking\u{2026}".to_string()
+        } else {
+            String::new()
+        };
+    }
+}
+
+/// Tell the players when they may claim a draw
+fn draw_claim_text_update(game: ChangedRes<Game>, mut query: Query<(&mut Text, &DrawClaimText)>) {
+    for (mut text, _tag) in query.iter_mut() {
+        text.value = match game.claimable_draw() {
+            Some(reason) => format!("Press D to claim a draw ({})", reason_text(reason)),
+            None => String::new(),
+        };
+    }
+}
+
+/// Show which game and move of a replay is on the board
+fn replay_text_update(replay: ChangedRes<Replay>, mut query: Query<(&mut Text, &ReplayText)>) {
+    let game = match replay.current() {
+        Some(game) => game,
+        None => return,
+    };
+
+    let (game_number, games) = replay.game_number();
+    let (ply, plies) = replay.ply();
+    for (mut text, _tag) in query.iter_mut() {
+        text.value = format!(
+            "Replay {}/{}: {} vs {}, half move {}/{} ({})",
+            game_number,
+            games,
+            game.tag("White").unwrap_or("?"),
+            game.tag("Black").unwrap_or("?"),
+            ply,
+            plies,
+            game.result
+        );
+    }
+}
+
+/// Moves of the external engine's principal variation shown at most
+const ANALYSIS_MOVES: usize = 8;
+
+/// Show the external engine's latest depth, score from White's side and best line
+fn analysis_text_update(
+    analysis: ChangedRes<ExternalAnalysis>,
+    mut query: Query<(&mut Text, &AnalysisText)>,
+) {
+    let info = match &analysis.info {
+        Some(info) => info,
+        None => return,
+    };
+
+    let sign = match analysis.position.turn() {
+        PieceColor::White => 1,
+        PieceColor::Black => -1,
+    };
+    let score = match info.score {
+        Some(Score::Centipawns(centipawns)) => format!("{:+.2}", (sign * centipawns) as f32 / 100.),
+        Some(Score::Mate(moves)) => format!("#{}", sign * moves),
+        None => "?".to_string(),
+    };
+    // The line in SAN, as far as it makes sense
+    let mut position = analysis.position.clone();
+    let mut line = Vec::new();
+    for uci in info.pv.iter().take(ANALYSIS_MOVES) {
+        let mv = match parse_uci(&position, uci) {
+            Some(mv) => mv,
+            None => break,
+        };
+        line.push(to_san(&position, mv));
+        position.apply_move(mv);
+    }
+
+    for (mut text, _tag) in query.iter_mut() {
+        text.value = format!(
+            "{}: depth {}, {} {}",
+            analysis.engine_name,
+            info.depth.unwrap_or(0),
+            score,
+            line.join(" ")
+        );
+    }
+}
+
+/// How a game ended, for humans
+fn reason_text(reason: GameOverReason) -> &'static str {
+    match reason {
+        GameOverReason::Checkmate => "Checkmate",
+        GameOverReason::Stalemate => "Stalemate",
+        GameOverReason::InsufficientMaterial => "Insufficient material",
+        GameOverReason::FiftyMoveRule => "Fifty-move rule",
+        GameOverReason::SeventyFiveMoveRule => "Seventy-five-move rule",
+        GameOverReason::ThreefoldRepetition => "Threefold repetition",
+        GameOverReason::FivefoldRepetition => "Fivefold repetition",
+        GameOverReason::Timeout => "Out of time",
+        GameOverReason::TimeoutVsInsufficientMaterial => "Out of time, but no mating material",
+    }
+}
+
+// Component to mark the result screen
+struct GameOverScreen;
+
+/// Cover the board with the result once the game is over. The app keeps running.
+fn game_over_screen(
+    commands: &mut Commands,
+    mut event_reader: Local<EventReader<GameOver>>,
+    events: Res<Events<GameOver>>,
+    asset_server: ResMut<AssetServer>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+) {
+    for game_over in event_reader.iter(&events) {
+        let result = match game_over.result.winner() {
+            Some(PieceColor::White) => "White wins",
+            Some(PieceColor::Black) => "Black wins",
+            None => "Draw",
+        };
+        let reason = reason_text(game_over.reason);
+        println!("{}! {}. Thanks for playing!", reason, result);
+
+        commands
+            .spawn(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                    position_type: PositionType::Absolute,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..Default::default()
+                },
+                material: color_materials.add(Color::rgba(0., 0., 0., 0.6).into()),
+                ..Default::default()
+            })
+            .with(GameOverScreen)
+            .with_children(|parent| {
+                parent.spawn(TextBundle {
+                    text: Text {
+                        value: format!("{}! {}", reason, result),
+                        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                        style: TextStyle {
+                            font_size: 80.0,
+                            color: Color::rgb(0.9, 0.9, 0.9),
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                });
+            });
+    }
+}
+
+/// Take the result screen away again once a move is undone
+fn clear_game_over_screen(
+    commands: &mut Commands,
+    game: ChangedRes<Game>,
+    screen_query: Query<Entity, With<GameOverScreen>>,
+) {
+    if game.outcome().is_some() {
+        return;
+    }
+    for entity in screen_query.iter() {
+        commands.despawn_recursive(entity);
+    }
+}
+
+// Component to mark the promotion dialog and the piece each of its buttons stands for
+struct PromotionDialog;
+struct PromotionButton(PieceType);
+
+/// Show the promotion choices while a promotion is pending, and hide them once it isn't
+fn promotion_dialog(
+    commands: &mut Commands,
+    pending_promotion: ChangedRes<PendingPromotion>,
+    asset_server: ResMut<AssetServer>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+    dialog_query: Query<Entity, With<PromotionDialog>>,
+) {
+    for entity in dialog_query.iter() {
+        commands.despawn_recursive(entity);
+    }
+    if pending_promotion.0.is_none() {
+        return;
+    }
+
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let button_material = color_materials.add(Color::rgb(0.15, 0.15, 0.15).into());
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: color_materials.add(Color::NONE.into()),
+            ..Default::default()
+        })
+        .with(PromotionDialog)
+        .with_children(|parent| {
+            for piece_type in PROMOTION_TYPES.iter() {
+                parent
+                    .spawn(ButtonBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(180.), Val::Px(80.)),
+                            margin: Rect::all(Val::Px(10.)),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..Default::default()
+                        },
+                        material: button_material.clone(),
+                        ..Default::default()
+                    })
+                    .with(PromotionButton(*piece_type))
+                    .with_children(|parent| {
+                        parent.spawn(TextBundle {
+                            text: Text {
+                                value: format!("{:?}", piece_type),
+                                font: font.clone(),
+                                style: TextStyle {
+                                    font_size: 40.0,
+                                    color: Color::rgb(0.9, 0.9, 0.9),
+                                    ..Default::default()
+                                },
+                            },
+                            ..Default::default()
+                        });
+                    });
+            }
+        });
+}
+
+/// Finish the pending promotion with the piece the player clicked
+fn promotion_buttons(
+    mut pending_promotion: ResMut<PendingPromotion>,
+    mut play_move_events: ResMut<Events<PlayMove>>,
+    interaction_query: Query<(&Interaction, &PromotionButton), Mutated<Interaction>>,
+) {
+    for (interaction, button) in interaction_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        if let Some(mv) = pending_promotion.0.take() {
+            play_move_events.send(PlayMove(Move::with_promotion(mv.from, mv.to, button.0)));
+        }
+    }
+}
+
+// Settings ===================================================================================== //
+
+// Component to mark the button opening the settings, the settings panel and its texts
+struct SettingsButton;
+struct SettingsPanel;
+struct HashSizeText;
+
+/// Doubles the engine's transposition table if `.0`, halves it otherwise
+struct HashSizeButton(bool);
+
+fn hash_size_label(megabytes: usize) -> String {
+    format!("Hash: {} MB", megabytes)
+}
+
+fn init_settings(
+    commands: &mut Commands,
+    asset_server: ResMut<AssetServer>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    commands
+        .spawn(ButtonBundle {
+            style: Style {
+                size: Size::new(Val::Px(130.), Val::Px(40.)),
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.),
+                    bottom: Val::Px(10.),
+                    ..Default::default()
+                },
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: color_materials.add(Color::rgb(0.15, 0.15, 0.15).into()),
+            ..Default::default()
+        })
+        .with(SettingsButton)
+        .with_children(|parent| {
+            parent.spawn(TextBundle {
+                text: Text {
+                    value: "Settings".to_string(),
+                    font,
+                    style: TextStyle {
+                        font_size: 30.0,
+                        color: Color::rgb(0.9, 0.9, 0.9),
+                        ..Default::default()
+                    },
+                },
+                ..Default::default()
+            });
+        });
+}
+
+/// Open the settings panel, or close it if it is open
+fn settings_button(
+    commands: &mut Commands,
+    opponent: Res<EngineOpponent>,
+    asset_server: ResMut<AssetServer>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+    interaction_query: Query<&Interaction, (With<SettingsButton>, Mutated<Interaction>)>,
+    panel_query: Query<Entity, With<SettingsPanel>>,
+) {
+    if !interaction_query
+        .iter()
+        .any(|interaction| *interaction == Interaction::Clicked)
+    {
+        return;
+    }
+    let mut was_open = false;
+    for entity in panel_query.iter() {
+        commands.despawn_recursive(entity);
+        was_open = true;
+    }
+    if was_open {
+        return;
+    }
+
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let button_material = color_materials.add(Color::rgb(0.15, 0.15, 0.15).into());
+    let text_style = TextStyle {
+        font_size: 30.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+        ..Default::default()
+    };
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.),
+                    bottom: Val::Px(60.),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::Row,
+                align_items: AlignItems::Center,
+                padding: Rect::all(Val::Px(5.)),
+                ..Default::default()
+            },
+            material: color_materials.add(Color::rgba(0.1, 0.1, 0.1, 0.8).into()),
+            ..Default::default()
+        })
+        .with(SettingsPanel)
+        .with_children(|parent| {
+            parent
+                .spawn(TextBundle {
+                    text: Text {
+                        value: hash_size_label(opponent.hash_size()),
+                        font: font.clone(),
+                        style: text_style.clone(),
+                    },
+                    style: Style {
+                        margin: Rect::all(Val::Px(5.)),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .with(HashSizeText);
+            for (label, bigger) in [("-", false), ("+", true)].iter() {
+                parent
+                    .spawn(ButtonBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(40.), Val::Px(40.)),
+                            margin: Rect::all(Val::Px(5.)),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..Default::default()
+                        },
+                        material: button_material.clone(),
+                        ..Default::default()
+                    })
+                    .with(HashSizeButton(*bigger))
+                    .with_children(|parent| {
+                        parent.spawn(TextBundle {
+                            text: Text {
+                                value: label.to_string(),
+                                font: font.clone(),
+                                style: text_style.clone(),
+                            },
+                            ..Default::default()
+                        });
+                    });
+            }
+        });
+}
+
+/// Resize the engine's transposition table, in powers of two
+fn hash_size_buttons(
+    mut opponent: ResMut<EngineOpponent>,
+    interaction_query: Query<(&Interaction, &HashSizeButton), Mutated<Interaction>>,
+    mut text_query: Query<&mut Text, With<HashSizeText>>,
+) {
+    for (interaction, button) in interaction_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        let megabytes = opponent.hash_size();
+        let megabytes = if button.0 {
+            (megabytes * 2).min(MAX_HASH_MB)
+        } else {
+            (megabytes / 2).max(MIN_HASH_MB)
+        };
+        if megabytes != opponent.hash_size() {
+            opponent.set_hash_size(megabytes);
+        }
+        for mut text in text_query.iter_mut() {
+            text.value = hash_size_label(megabytes);
+        }
+    }
+}
+
+// Move list ================================================================================== //
+
+/// Rows of the move list shown at once, the mouse wheel scrolls through the rest
+const MOVE_LIST_ROWS: usize = 20;
+
+// Component to mark the move list panel and its rows
+struct MoveListPanel;
+struct MoveListRow;
+
+/// Clicking the entry shows the position after `.0` half moves
+struct MoveListButton(usize);
+
+struct MoveListMaterials {
+    panel: Handle<ColorMaterial>,
+    entry: Handle<ColorMaterial>,
+    current: Handle<ColorMaterial>,
+}
+
+impl FromResources for MoveListMaterials {
+    fn from_resources(resources: &Resources) -> Self {
+        let mut materials = resources.get_mut::<Assets<ColorMaterial>>().unwrap();
+        MoveListMaterials {
+            panel: materials.add(Color::rgba(0.1, 0.1, 0.1, 0.8).into()),
+            entry: materials.add(Color::NONE.into()),
+            current: materials.add(Color::rgb(0.3, 0.4, 0.6).into()),
+        }
+    }
+}
+
+/// What the move list was last built from
+#[derive(Default)]
+struct MoveListState {
+    moves: Vec<Move>,
+    shown_ply: usize,
+    first_row: usize,
+    built: bool,
+}
+
+fn init_move_list(commands: &mut Commands, materials: Res<MoveListMaterials>) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    right: Val::Px(10.),
+                    top: Val::Px(10.),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                padding: Rect::all(Val::Px(5.)),
+                ..Default::default()
+            },
+            material: materials.panel.clone(),
+            ..Default::default()
+        })
+        .with(MoveListPanel);
+}
+
+/// Rebuild the move list when the moves, the position on the board or the scrolling change.
+/// Rows hold numbered move pairs in SAN, the move leading to the position on the board is
+/// highlighted.
+fn move_list_update(
+    commands: &mut Commands,
+    game: Res<Game>,
+    review: Res<ReviewPly>,
+    mut state: Local<MoveListState>,
+    mut wheel_reader: Local<EventReader<MouseWheel>>,
+    wheel_events: Res<Events<MouseWheel>>,
+    asset_server: Res<AssetServer>,
+    materials: Res<MoveListMaterials>,
+    panel_query: Query<Entity, With<MoveListPanel>>,
+    row_query: Query<Entity, With<MoveListRow>>,
+) {
+    let moves = game.moves();
+    let shown_ply = review.0.unwrap_or_else(|| moves.len());
+
+    // A game starting with Black's move leaves White's first entry empty
+    let start = game.starting_position();
+    let offset = match start.turn() {
+        PieceColor::White => 0,
+        PieceColor::Black => 1,
+    };
+    let rows = (moves.len() + offset + 1) / 2;
+    let max_first_row = rows.saturating_sub(MOVE_LIST_ROWS);
+
+    let mut first_row = state.first_row;
+    if moves != state.moves.as_slice() || shown_ply != state.shown_ply {
+        // Keep the highlighted move in view
+        if shown_ply > 0 {
+            let row = (shown_ply - 1 + offset) / 2;
+            if row < first_row {
+                first_row = row;
+            } else if row >= first_row + MOVE_LIST_ROWS {
+                first_row = row + 1 - MOVE_LIST_ROWS;
+            }
+        }
+    }
+    for wheel in wheel_reader.iter(&wheel_events) {
+        if wheel.y > 0. {
+            first_row = first_row.saturating_sub(1);
+        } else if wheel.y < 0. {
+            first_row += 1;
+        }
+    }
+    first_row = first_row.min(max_first_row);
+
+    if state.built
+        && moves == state.moves.as_slice()
+        && shown_ply == state.shown_ply
+        && first_row == state.first_row
+    {
+        return;
+    }
+    *state = MoveListState {
+        moves: moves.to_vec(),
+        shown_ply,
+        first_row,
+        built: true,
+    };
+
+    let panel = match panel_query.iter().next() {
+        Some(panel) => panel,
+        None => return,
+    };
+    for row in row_query.iter() {
+        commands.despawn_recursive(row);
+    }
+
+    // Name every move in the position it was played in
+    let mut position = start.clone();
+    let mut entries: Vec<Option<(usize, String)>> = vec![None; offset];
+    for (i, mv) in moves.iter().enumerate() {
+        entries.push(Some((i + 1, to_san(&position, *mv))));
+        position.apply_move(*mv);
+    }
+
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let text = |value: String, color: Color| TextBundle {
+        text: Text {
+            value,
+            font: font.clone(),
+            style: TextStyle {
+                font_size: 24.0,
+                color,
+                ..Default::default()
+            },
+        },
+        ..Default::default()
+    };
+    commands.set_current_entity(panel);
+    commands.with_children(|parent| {
+        for (row, pair) in entries
+            .chunks(2)
+            .enumerate()
+            .skip(first_row)
+            .take(MOVE_LIST_ROWS)
+        {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        align_items: AlignItems::Center,
+                        ..Default::default()
+                    },
+                    material: materials.entry.clone(),
+                    ..Default::default()
+                })
+                .with(MoveListRow)
+                .with_children(|parent| {
+                    parent
+                        .spawn(NodeBundle {
+                            style: Style {
+                                size: Size::new(Val::Px(50.), Val::Px(28.)),
+                                align_items: AlignItems::Center,
+                                ..Default::default()
+                            },
+                            material: materials.entry.clone(),
+                            ..Default::default()
+                        })
+                        .with_children(|parent| {
+                            parent.spawn(text(
+                                format!("{}.", start.fullmove_number() as usize + row),
+                                Color::rgb(0.6, 0.6, 0.6),
+                            ));
+                        });
+                    for entry in pair {
+                        parent.spawn(ButtonBundle {
+                            style: Style {
+                                size: Size::new(Val::Px(110.), Val::Px(28.)),
+                                padding: Rect::all(Val::Px(3.)),
+                                align_items: AlignItems::Center,
+                                ..Default::default()
+                            },
+                            material: match entry {
+                                Some((ply, _)) if *ply == shown_ply => materials.current.clone(),
+                                _ => materials.entry.clone(),
+                            },
+                            ..Default::default()
+                        });
+                        if let Some((ply, san)) = entry {
+                            parent.with(MoveListButton(*ply)).with_children(|parent| {
+                                parent.spawn(text(san.clone(), Color::rgb(0.9, 0.9, 0.9)));
+                            });
+                        }
+                    }
+                });
+        }
+    });
+}
+
+/// Show the position after the clicked move, or go back to playing when it is the last one
+fn move_list_buttons(
+    game: Res<Game>,
+    mut review: ResMut<ReviewPly>,
+    mut resync_events: ResMut<Events<ResyncPieces>>,
+    interaction_query: Query<(&Interaction, &MoveListButton), Mutated<Interaction>>,
+) {
+    for (interaction, button) in interaction_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        let ply = if button.0 < game.moves().len() {
+            Some(button.0)
+        } else {
+            None
+        };
+        if ply != review.0 {
+            review.0 = ply;
+            resync_events.send(ResyncPieces);
+        }
     }
 }
 
 pub struct UIPlugin;
 impl Plugin for UIPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_startup_system(init_next_move_text.system())
+        app.init_resource::<MoveListMaterials>()
+            .add_startup_system(init_next_move_text.system())
+            .add_startup_system(init_move_list.system())
+            .add_startup_system(init_settings.system())
             .add_system(next_move_text_update.system())
-            .add_system(log_text_changes.system());
+            .add_system(clock_text_update.system())
+            .add_system(thinking_text_update.system())
+            .add_system(draw_claim_text_update.system())
+            .add_system(replay_text_update.system())
+            .add_system(analysis_text_update.system())
+            .add_system(game_over_screen.system())
+            .add_system(clear_game_over_screen.system())
+            .add_system(undo_redo_buttons.system())
+            .add_system(promotion_buttons.system())
+            .add_system(promotion_dialog.system())
+            .add_system(move_list_buttons.system())
+            .add_system(move_list_update.system())
+            .add_system(settings_button.system())
+            .add_system(hash_size_buttons.system());
     }
-}
\ No newline at end of file
+}